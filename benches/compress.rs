@@ -0,0 +1,86 @@
+//! Timing of `Definition::compress` for growing inputs; the time per
+//! prefix should stay (roughly) constant.
+//!
+//! Run with `cargo bench`; with `--features parallel` the timing of
+//! `Definition::compress_parallel` (using all CPUs) is shown too.
+extern crate compress_cidr;
+
+use compress_cidr::{cidr,Definition,RadixSet};
+use compress_cidr::cidr::Cidr;
+use std::hint::black_box;
+use std::net::Ipv6Addr;
+use std::time::Instant;
+
+// simple deterministic pseudo random numbers (64-bit LCG)
+struct Random(u64);
+
+impl Random {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+		self.0
+	}
+}
+
+fn prefix(addr: u128, len: u8) -> cidr::Ipv6Cidr {
+	let mask = if 0 == len { 0 } else { !0u128 << (128 - len) };
+	cidr::Ipv6Cidr::new(Ipv6Addr::from(addr & mask), len).unwrap()
+}
+
+// random /32 to /64 networks all over the address space
+fn spread(count: usize, rnd: &mut Random) -> RadixSet<cidr::Ipv6Cidr> {
+	let mut set = RadixSet::default();
+	for _ in 0..count {
+		let addr = ((rnd.next() as u128) << 64) | rnd.next() as u128;
+		set.insert(prefix(addr, 32 + (rnd.next() % 33) as u8));
+	}
+	set
+}
+
+// random /64 networks and single addresses within one /32, with
+// neighbouring ranges left out (deep and unbalanced tree)
+fn clustered(count: usize, rnd: &mut Random) -> RadixSet<cidr::Ipv6Cidr> {
+	let base = 0x2001_0db8u128 << 96;
+	let mut set = RadixSet::default();
+	for _ in 0..count {
+		let addr = base | ((rnd.next() as u128 & 0xffff_ffff) << 64) | rnd.next() as u128;
+		let len = if rnd.next() & 1 == 0 { 64 } else { 128 };
+		set.insert(prefix(addr, len));
+	}
+	set
+}
+
+fn report(name: &str, count: usize, invert: bool, rules: usize, start: Instant) {
+	let elapsed = start.elapsed();
+	println!(
+		"{:<13} {:>9} prefixes {:<7} {:>9} rules {:>8.1} ms {:>7.1} ns/prefix",
+		name, count, if invert { "invert" } else { "" }, rules,
+		elapsed.as_secs_f64() * 1e3, elapsed.as_secs_f64() * 1e9 / count as f64,
+	);
+}
+
+fn bench<F>(name: &str, generate: F)
+where
+	F: Fn(usize, &mut Random) -> RadixSet<cidr::Ipv6Cidr>,
+{
+	for &count in &[1_000_000, 2_000_000, 4_000_000] {
+		let set = generate(count, &mut Random(count as u64));
+		for &invert in &[false, true] {
+			let start = Instant::now();
+			let defs = black_box(Definition::compress(black_box(&set), invert));
+			report(name, count, invert, defs.len(), start);
+			#[cfg(feature = "parallel")]
+			{
+				let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+				let start = Instant::now();
+				let parallel = black_box(Definition::compress_parallel(black_box(&set), invert, threads));
+				report(&format!("{} x{}", name, threads), count, invert, parallel.len(), start);
+				assert!(parallel == defs);
+			}
+		}
+	}
+}
+
+fn main() {
+	bench("spread", spread);
+	bench("clustered", clustered);
+}
@@ -0,0 +1,81 @@
+//! Timing of `SetReader::read` for inputs with 10 million lines,
+//! compared with reading a `String` per line and parsing it with
+//! `FromStr`.
+//!
+//! Run with `cargo bench --bench read`.
+extern crate compress_cidr;
+
+use compress_cidr::{cidr,input,RadixSet};
+use compress_cidr::input::{InvalidLinePolicy,SetReader};
+use std::hint::black_box;
+use std::io::BufRead;
+use std::str::FromStr;
+use std::time::Instant;
+
+const LINES: usize = 10_000_000;
+
+// simple deterministic pseudo random numbers (64-bit LCG)
+struct Random(u64);
+
+impl Random {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+		self.0
+	}
+}
+
+// random /24 to /32 IPv4 networks, one per line
+fn ipv4_lines(rnd: &mut Random) -> Vec<u8> {
+	let mut text = Vec::new();
+	for _ in 0..LINES {
+		let len = 24 + (rnd.next() >> 32) % 9;
+		let addr = (rnd.next() >> 32) as u32 & (!0u32 << (32 - len));
+		text.extend_from_slice(format!("{}/{}\n", std::net::Ipv4Addr::from(addr), len).as_bytes());
+	}
+	text
+}
+
+// random /32 to /64 IPv6 networks, one per line
+fn ipv6_lines(rnd: &mut Random) -> Vec<u8> {
+	let mut text = Vec::new();
+	for _ in 0..LINES {
+		let len = 32 + (rnd.next() >> 32) % 33;
+		let addr = ((rnd.next() as u128) << 64) & (!0u128 << (128 - len));
+		text.extend_from_slice(format!("{}/{}\n", std::net::Ipv6Addr::from(addr), len).as_bytes());
+	}
+	text
+}
+
+fn report(name: &str, text: &[u8], prefixes: usize, start: Instant) {
+	let elapsed = start.elapsed();
+	println!(
+		"{:<13} {:>9} lines {:>8.1} MB {:>9} prefixes {:>8.1} ms {:>7.1} ns/line",
+		name, LINES, text.len() as f64 / 1e6, prefixes,
+		elapsed.as_secs_f64() * 1e3, elapsed.as_secs_f64() * 1e9 / LINES as f64,
+	);
+}
+
+fn bench<C>(name: &str, text: &[u8])
+where
+	C: compress_cidr::IpPrefix+FromStr,
+	<C as FromStr>::Err: std::fmt::Debug,
+{
+	let start = Instant::now();
+	let mut reader = SetReader::<C>::new(InvalidLinePolicy::Strict);
+	reader.read("<bench>", black_box(text)).unwrap();
+	report(name, text, black_box(reader.into_set()).iter().count(), start);
+
+	let start = Instant::now();
+	let mut set = RadixSet::<C>::default();
+	for (line_no, line) in black_box(text).lines().enumerate() {
+		if let Some(prefix) = input::parse_line(name, line_no + 1, &line.unwrap()) {
+			set.insert(prefix.unwrap());
+		}
+	}
+	report(&format!("{} lines()", name), text, black_box(set).iter().count(), start);
+}
+
+fn main() {
+	bench::<cidr::Ipv4Cidr>("ipv4", &ipv4_lines(&mut Random(4)));
+	bench::<cidr::Ipv6Cidr>("ipv6", &ipv6_lines(&mut Random(6)));
+}
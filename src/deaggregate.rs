@@ -0,0 +1,135 @@
+//! expand a set into prefixes of a fixed length (the opposite of
+//! aggregation)
+use bitstring::BitString;
+use bitstring_trees::set::{self,RadixSet};
+use limits::TooLong;
+use prefix::IpPrefix;
+use std::cmp::min;
+
+/// Iterator over the prefixes of a fixed length covering a set (see
+/// `deaggregate`)
+///
+/// Prefixes are generated one by one; only the current prefix is kept
+/// in memory.
+pub struct Deaggregate<'a, S: BitString+'a> {
+	iter: set::Iter<'a, S>,
+	len: usize,
+	// prefixes waiting to be expanded (last one first)
+	pending: Vec<S>,
+	// length of the prefix being expanded, and the next prefix of it
+	current: Option<(usize, S)>,
+}
+
+impl<'a, S: IpPrefix+'a> Deaggregate<'a, S> {
+	fn new(set: &'a RadixSet<S>, len: usize) -> Self {
+		Deaggregate{
+			iter: set.iter(),
+			len,
+			pending: Vec::new(),
+			current: None,
+		}
+	}
+}
+
+// next prefix of the same length with the same first `from` bits
+fn next_prefix<S: BitString+Clone>(key: &S, from: usize) -> Option<S> {
+	let d = (from..key.len()).rev().find(|&d| !key.get(d))?;
+	let mut next = key.clone();
+	next.set(d, true);
+	for i in d + 1..next.len() {
+		next.set(i, false);
+	}
+	Some(next)
+}
+
+impl<'a, S: IpPrefix+'a> Iterator for Deaggregate<'a, S> {
+	type Item = S;
+
+	fn next(&mut self) -> Option<S> {
+		loop {
+			if let Some((from, key)) = self.current.take() {
+				self.current = next_prefix(&key, from).map(|next| (from, next));
+				return Some(key);
+			}
+			let prefix = match self.pending.pop() {
+				Some(p) => p,
+				None => self.iter.next()?.clone(),
+			};
+			let target = match prefix.prefix_address_length() {
+				Some(a) => S::FAMILY_BITS + min(self.len, a),
+				None => {
+					// address family not selected yet
+					let mut right = prefix.clone();
+					right.append(true);
+					let mut left = prefix;
+					left.append(false);
+					self.pending.push(right);
+					self.pending.push(left);
+					continue;
+				},
+			};
+			if prefix.len() >= target {
+				return Some(prefix);
+			}
+			let from = prefix.len();
+			let mut first = prefix;
+			while first.len() < target {
+				first.append(false);
+			}
+			self.current = Some((from, first));
+		}
+	}
+}
+
+/// Iterates over all prefixes of network length `len` covering the
+/// set, in lexicographic order; prefixes of the set which are longer
+/// are returned unchanged (i.e. all returned prefixes have a network
+/// length of at least `len`).
+///
+/// If `len` is larger than the address length, single addresses are
+/// returned instead.  The number of returned prefixes can be huge (see
+/// `deaggregate_count`).
+pub fn deaggregate<S: IpPrefix>(set: &RadixSet<S>, len: usize) -> Deaggregate<'_, S> {
+	Deaggregate::new(set, len)
+}
+
+/// Same as `deaggregate`, but fails if the set contains prefixes
+/// longer than `len` (i.e. all returned prefixes have a network length
+/// of exactly `len`, or are single addresses).
+pub fn deaggregate_exact<S: IpPrefix>(set: &RadixSet<S>, len: usize) -> Result<Deaggregate<'_, S>, TooLong<S>> {
+	match set.iter().find(|p| p.prefix_network_length().is_some_and(|l| l > len)) {
+		Some(prefix) => Err(TooLong{
+			prefix: prefix.clone(),
+			max: len,
+		}),
+		None => Ok(Deaggregate::new(set, len)),
+	}
+}
+
+// number of prefixes `deaggregate` returns for a single prefix
+fn prefix_count<S: IpPrefix>(prefix: &S, len: usize) -> u128 {
+	match prefix.prefix_address_length() {
+		Some(a) => {
+			let target = S::FAMILY_BITS + min(len, a);
+			if prefix.len() >= target {
+				1
+			} else if target - prefix.len() >= 128 {
+				u128::MAX
+			} else {
+				1u128 << (target - prefix.len())
+			}
+		},
+		None => {
+			let mut right = prefix.clone();
+			right.append(true);
+			let mut left = prefix.clone();
+			left.append(false);
+			prefix_count(&left, len).saturating_add(prefix_count(&right, len))
+		},
+	}
+}
+
+/// Number of prefixes `deaggregate` returns; saturates at `u128::MAX`.
+pub fn deaggregate_count<S: IpPrefix>(set: &RadixSet<S>, len: usize) -> u128 {
+	set.iter().fold(0u128, |sum, p| sum.saturating_add(prefix_count(p, len)))
+}
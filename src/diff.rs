@@ -0,0 +1,45 @@
+//! differences between two sets
+use bitstring::BitString;
+use bitstring_trees::set::RadixSet;
+use prefix::IpPrefix;
+use set_def::Definition;
+use set_ops;
+
+/// Result of `diff`
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct SetDiff<S: BitString> {
+	/// aggregated prefixes only in the old set
+	pub removed: Vec<S>,
+	/// aggregated prefixes only in the new set
+	pub added: Vec<S>,
+	/// number of addresses only in the old set; saturates at
+	/// `u128::MAX`
+	pub removed_addresses: u128,
+	/// number of addresses only in the new set; saturates at
+	/// `u128::MAX`
+	pub added_addresses: u128,
+}
+
+// aggregated prefixes (the including part of `Definition::complete`)
+// and their number of addresses
+fn aggregate<S: IpPrefix>(s: &RadixSet<S>) -> (Vec<S>, u128) {
+	let prefixes : Vec<S> = Definition::complete(s, false).into_iter()
+		.filter(|def| def.label)
+		.map(|def| def.prefix)
+		.collect();
+	let addresses = prefixes.iter().fold(0u128, |sum, p| sum.saturating_add(p.address_count()));
+	(prefixes, addresses)
+}
+
+/// Compares an old and a new set; both lists of prefixes are sorted
+/// lexicographically.
+pub fn diff<S: IpPrefix>(old: &RadixSet<S>, new: &RadixSet<S>) -> SetDiff<S> {
+	let (removed, removed_addresses) = aggregate(&set_ops::difference(old, new));
+	let (added, added_addresses) = aggregate(&set_ops::difference(new, old));
+	SetDiff{
+		removed,
+		added,
+		removed_addresses,
+		added_addresses,
+	}
+}
@@ -0,0 +1,73 @@
+//! address families of mixed sets
+use bitstring_trees::set::RadixSet;
+use cidr::{AnyIpCidr,Cidr,Ipv4Cidr,Ipv6Cidr};
+use std::net::{Ipv4Addr,Ipv6Addr};
+
+/// Splits a mixed set into its IPv4 and IPv6 parts; the whole address
+/// space (`AnyIpCidr::Any`) is split into `0.0.0.0/0` and `::/0`.
+pub fn split(set: &RadixSet<AnyIpCidr>) -> (RadixSet<Ipv4Cidr>, RadixSet<Ipv6Cidr>) {
+	let mut ipv4 = RadixSet::default();
+	let mut ipv6 = RadixSet::default();
+	for prefix in set.iter() {
+		match *prefix {
+			AnyIpCidr::Any => {
+				ipv4.insert(Ipv4Cidr::new(Ipv4Addr::new(0, 0, 0, 0), 0).unwrap());
+				ipv6.insert(Ipv6Cidr::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 0).unwrap());
+			},
+			AnyIpCidr::V4(ref c) => ipv4.insert(c.clone()),
+			AnyIpCidr::V6(ref c) => ipv6.insert(c.clone()),
+		}
+	}
+	(ipv4, ipv6)
+}
+
+/// IPv6 ranges embedding IPv4 addresses
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum Ipv4Embedding {
+	/// IPv4-mapped addresses `::ffff:0:0/96`
+	Mapped,
+	/// 6to4 networks `2002::/16` (the IPv4 address follows the first
+	/// 16 bits)
+	SixToFour,
+	/// NAT64 well-known prefix `64:ff9b::/96`
+	Nat64,
+	/// deprecated IPv4-compatible addresses `::/96` (this includes the
+	/// unspecified address `::` and the loopback address `::1`)
+	Compatible,
+}
+
+impl Ipv4Embedding {
+	// the embedding IPv6 prefix; the IPv4 address follows it
+	fn prefix(self) -> (u128, usize) {
+		match self {
+			Ipv4Embedding::Mapped => (0xffff << 32, 96),
+			Ipv4Embedding::SixToFour => (0x2002 << 112, 16),
+			Ipv4Embedding::Nat64 => (0x64_ff9b << 96, 96),
+			Ipv4Embedding::Compatible => (0, 96),
+		}
+	}
+
+	/// The IPv4 prefix embedded in `prefix`; `None` if `prefix` isn't
+	/// within the embedding prefix, or (for 6to4) is longer than `/48`
+	/// (i.e. only covers a part of the 6to4 network of an address).
+	pub fn fold(self, prefix: &Ipv6Cidr) -> Option<Ipv4Cidr> {
+		let (base, base_len) = self.prefix();
+		let len = prefix.network_length() as usize;
+		if len < base_len || len > base_len + 32 {
+			return None;
+		}
+		let addr = u128::from(prefix.first_address());
+		if 0 != (addr ^ base) >> (128 - base_len) {
+			return None;
+		}
+		let ipv4 = Ipv4Addr::from((addr >> (96 - base_len)) as u32);
+		Some(Ipv4Cidr::new(ipv4, (len - base_len) as u8).unwrap())
+	}
+
+	/// The IPv6 prefix embedding `prefix`
+	pub fn lift(self, prefix: &Ipv4Cidr) -> Ipv6Cidr {
+		let (base, base_len) = self.prefix();
+		let addr = base | u128::from(u32::from(prefix.first_address())) << (96 - base_len);
+		Ipv6Cidr::new(Ipv6Addr::from(addr), base_len as u8 + prefix.network_length()).unwrap()
+	}
+}
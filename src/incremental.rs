@@ -0,0 +1,487 @@
+//! incremental compression of a changing set
+use bitstring::BitString;
+use bitstring_trees::set::RadixSet;
+use set_def::{self,Combine,Definition};
+use std::cmp::Ordering;
+use std::mem;
+use update;
+
+// state a node was emitted with the last time `changes` ran; the
+// definitions a node emits itself only depend on it (and the key)
+#[derive(Clone,Copy,PartialEq)]
+struct Emitted {
+	from_len: usize,
+	positive: bool,
+	counts: (usize, usize),
+	combine: Combine,
+}
+
+struct Inner<S> {
+	key: S,
+	left: Node<S>,
+	right: Node<S>,
+	// length of the positive and negative list (see
+	// `set_def::combine_counts`)
+	pos: usize,
+	neg: usize,
+	emitted: Option<Emitted>,
+	// something in the subtree changed since `emitted` was recorded
+	dirty: bool,
+}
+
+enum Node<S> {
+	Leaf(S, Option<Emitted>),
+	InnerNode(Box<Inner<S>>),
+}
+
+impl<S: BitString+Clone> Node<S> {
+	fn key(&self) -> &S {
+		match *self {
+			Node::Leaf(ref key, _) => key,
+			Node::InnerNode(ref inner) => &inner.key,
+		}
+	}
+
+	fn new_inner(key_len: usize, a: Node<S>, b: Node<S>) -> Self {
+		let mut key = a.key().clone();
+		key.clip(key_len);
+		let (left, right) = if a.key().get(key_len) { (b, a) } else { (a, b) };
+		let mut inner = Inner{
+			key,
+			left,
+			right,
+			pos: 0,
+			neg: 0,
+			emitted: None,
+			dirty: true,
+		};
+		inner.update();
+		Node::InnerNode(Box::new(inner))
+	}
+
+	// length of the lists for a context starting at the key
+	fn inner_counts(&self) -> (usize, usize) {
+		match *self {
+			Node::Leaf(..) => (1, 0),
+			Node::InnerNode(ref inner) => (inner.pos, inner.neg),
+		}
+	}
+
+	// length of the lists for a context starting at `from_len`
+	fn branch_counts(&self, from_len: usize) -> (usize, usize) {
+		let (pos, neg) = self.inner_counts();
+		set_def::branch_counts(self.key().len() - from_len, pos, neg)
+	}
+
+	// definitions emitted by the node itself (without the children);
+	// returns whether the children are emitted positive
+	fn emit_local(&self, state: &Emitted, list: &mut Vec<Definition<S>>) -> bool {
+		let mut children_positive = false;
+		set_def::branch_emit(self.key(), state.from_len, state.positive, state.counts, list, |positive, list| {
+			match *self {
+				Node::Leaf(ref key, _) => if positive {
+					list.push(Definition{
+						prefix: key.clone(),
+						label: true,
+					});
+				},
+				Node::InnerNode(ref inner) => {
+					children_positive = set_def::combine_emit(&inner.key, state.combine, positive, list);
+				},
+			}
+		});
+		children_positive
+	}
+
+	fn emitted(&self) -> Option<Emitted> {
+		match *self {
+			Node::Leaf(_, emitted) => emitted,
+			Node::InnerNode(ref inner) => inner.emitted,
+		}
+	}
+
+	// retract everything the subtree emitted so far
+	fn forget(&mut self, removed: &mut Vec<Definition<S>>) {
+		if let Some(state) = self.emitted() {
+			self.emit_local(&state, removed);
+		}
+		match *self {
+			Node::Leaf(_, ref mut emitted) => *emitted = None,
+			Node::InnerNode(ref mut inner) => {
+				inner.emitted = None;
+				inner.dirty = true;
+				inner.left.forget(removed);
+				inner.right.forget(removed);
+			},
+		}
+	}
+
+	// update the emitted definitions of all nodes which changed
+	fn sync(&mut self, from_len: usize, positive: bool, removed: &mut Vec<Definition<S>>, added: &mut Vec<Definition<S>>) {
+		let state = Emitted{
+			from_len,
+			positive,
+			counts: self.inner_counts(),
+			combine: match *self {
+				Node::Leaf(..) => Combine::Append,
+				Node::InnerNode(ref inner) => inner.combine(),
+			},
+		};
+		let dirty = match *self {
+			Node::Leaf(..) => false,
+			Node::InnerNode(ref inner) => inner.dirty,
+		};
+		let old = self.emitted();
+		if !dirty && old == Some(state) {
+			// nothing in the subtree changed
+			return;
+		}
+		if let Some(old) = old {
+			self.emit_local(&old, removed);
+		}
+		let children_positive = self.emit_local(&state, added);
+		match *self {
+			Node::Leaf(_, ref mut emitted) => *emitted = Some(state),
+			Node::InnerNode(ref mut inner) => {
+				inner.emitted = Some(state);
+				inner.dirty = false;
+				let from_len = inner.key.len() + 1;
+				inner.left.sync(from_len, children_positive, removed, added);
+				inner.right.sync(from_len, children_positive, removed, added);
+			},
+		}
+	}
+
+	fn insert(&mut self, key: S, removed: &mut Vec<Definition<S>>) {
+		let self_key_len = self.key().len();
+		let shared_prefix_len = self.key().shared_prefix_len(&key);
+		if shared_prefix_len == key.len() {
+			// key contains the node: replace subtree
+			mem::replace(self, Node::Leaf(key, None)).forget(removed);
+			return;
+		}
+		let key_len = if shared_prefix_len < self_key_len {
+			let this = mem::replace(self, Node::Leaf(S::null(), None));
+			*self = Self::new_inner(shared_prefix_len, this, Node::Leaf(key, None));
+			shared_prefix_len
+		} else {
+			match *self {
+				Node::Leaf(..) => return, // already included
+				Node::InnerNode(ref mut inner) => {
+					if key.get(self_key_len) {
+						inner.right.insert(key, removed);
+					} else {
+						inner.left.insert(key, removed);
+					}
+				},
+			}
+			self_key_len
+		};
+		let full = match *self {
+			Node::InnerNode(ref mut inner) => {
+				inner.update();
+				match (&inner.left, &inner.right) {
+					(Node::Leaf(l, _), Node::Leaf(r, _)) => l.len() == key_len + 1 && r.len() == key_len + 1,
+					_ => false,
+				}
+			},
+			Node::Leaf(..) => false,
+		};
+		if full {
+			let mut key = self.key().clone();
+			key.clip(key_len);
+			mem::replace(self, Node::Leaf(key, None)).forget(removed);
+		}
+	}
+
+	// returns `None` if nothing is left
+	fn remove(mut self, key: &S, removed: &mut Vec<Definition<S>>) -> Option<Self> {
+		let self_key_len = self.key().len();
+		let shared_prefix_len = self.key().shared_prefix_len(key);
+		if shared_prefix_len == key.len() {
+			// key contains the node
+			self.forget(removed);
+			return None;
+		}
+		if shared_prefix_len < self_key_len {
+			// disjoint
+			return Some(self);
+		}
+		match self {
+			Node::Leaf(..) => {
+				self.forget(removed);
+				// keep the siblings of the path from the leaf to the key
+				let mut node : Option<Self> = None;
+				for d in (self_key_len..key.len()).rev() {
+					let mut sibling = key.clone();
+					sibling.clip(d + 1);
+					sibling.flip(d);
+					node = Some(match node {
+						None => Node::Leaf(sibling, None),
+						Some(n) => Self::new_inner(d, Node::Leaf(sibling, None), n),
+					});
+				}
+				node
+			},
+			Node::InnerNode(mut inner) => {
+				let inner_mut = &mut *inner;
+				let (side, other) = if key.get(self_key_len) {
+					(&mut inner_mut.right, &mut inner_mut.left)
+				} else {
+					(&mut inner_mut.left, &mut inner_mut.right)
+				};
+				let child = mem::replace(side, Node::Leaf(S::null(), None));
+				match child.remove(key, removed) {
+					None => {
+						let other = mem::replace(other, Node::Leaf(S::null(), None));
+						// the children are gone; only retracts the node itself
+						Node::InnerNode(inner).forget(removed);
+						return Some(other);
+					},
+					Some(child) => *side = child,
+				}
+				inner.update();
+				Some(Node::InnerNode(inner))
+			},
+		}
+	}
+
+	fn emit_inner(&self, positive: bool, list: &mut Vec<Definition<S>>) {
+		let inner = match *self {
+			Node::Leaf(ref key, _) => {
+				if positive {
+					list.push(Definition{
+						prefix: key.clone(),
+						label: true,
+					});
+				}
+				return;
+			},
+			Node::InnerNode(ref inner) => inner,
+		};
+		let from_len = inner.key.len() + 1;
+		let children_positive = set_def::combine_emit(&inner.key, inner.combine(), positive, list);
+		inner.left.emit_branch(from_len, children_positive, list);
+		inner.right.emit_branch(from_len, children_positive, list);
+	}
+
+	fn emit_branch(&self, from_len: usize, positive: bool, list: &mut Vec<Definition<S>>) {
+		set_def::branch_emit(self.key(), from_len, positive, self.inner_counts(), list, |positive, list| {
+			self.emit_inner(positive, list)
+		});
+	}
+}
+
+impl<S: BitString+Clone> Inner<S> {
+	fn children_counts(&self) -> (usize, usize) {
+		let from_len = self.key.len() + 1;
+		let (l_pos, l_neg) = self.left.branch_counts(from_len);
+		let (r_pos, r_neg) = self.right.branch_counts(from_len);
+		(l_pos + r_pos, l_neg + r_neg)
+	}
+
+	fn combine(&self) -> Combine {
+		let (pos, neg) = self.children_counts();
+		set_def::combine_counts(pos, neg).0
+	}
+
+	// recalculate the cached list lengths from the children
+	fn update(&mut self) {
+		let (pos, neg) = self.children_counts();
+		let (pos, neg) = set_def::combine_counts(pos, neg).1;
+		self.pos = pos;
+		self.neg = neg;
+		self.dirty = true;
+	}
+}
+
+/// Set which keeps the information needed by `Definition::compress`
+/// up to date while prefixes are inserted and removed.
+///
+/// Each node of the radix tree caches the length of its positive and
+/// negative definition list (all greedy decisions only depend on
+/// them); inserting or removing a prefix only updates the nodes on the
+/// path to it.  The number of definitions is available without
+/// generating them.
+///
+/// `compress` always generates the full list; `changes` only returns
+/// the definitions added and deleted since its last call.
+pub struct IncrementalSet<S: BitString> {
+	root: Option<Node<S>>,
+	// definitions of removed nodes emitted by the last `changes`
+	removed: Vec<Definition<S>>,
+	// `invert` of the last `changes` call
+	emitted_invert: Option<bool>,
+	// whether the last `changes` emitted `include` for an empty set
+	emitted_all: bool,
+}
+
+impl<S: BitString> Default for IncrementalSet<S> {
+	fn default() -> Self {
+		IncrementalSet{
+			root: None,
+			removed: Vec::new(),
+			emitted_invert: None,
+			emitted_all: false,
+		}
+	}
+}
+
+/// Result of `IncrementalSet::changes`
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct Changes<S: BitString> {
+	/// definitions which are new since the last call (sorted
+	/// lexicographically)
+	pub added: Vec<Definition<S>>,
+	/// definitions of the last call which are gone (sorted
+	/// lexicographically)
+	pub deleted: Vec<Definition<S>>,
+}
+
+fn flip<S: BitString>(list: &mut [Definition<S>]) {
+	for def in list {
+		def.label = !def.label;
+	}
+}
+
+impl<'a, S: BitString+Clone> From<&'a RadixSet<S>> for IncrementalSet<S> {
+	fn from(s: &'a RadixSet<S>) -> Self {
+		let mut set = Self::default();
+		for prefix in s.iter() {
+			set.insert(prefix.clone());
+		}
+		set
+	}
+}
+
+impl<S: BitString+Clone> IncrementalSet<S> {
+	/// New (empty) set
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Whether the set is empty
+	pub fn is_empty(&self) -> bool {
+		self.root.is_none()
+	}
+
+	/// Include all addresses of `prefix`
+	pub fn insert(&mut self, prefix: S) {
+		match self.root {
+			None => self.root = Some(Node::Leaf(prefix, None)),
+			Some(ref mut node) => node.insert(prefix, &mut self.removed),
+		}
+	}
+
+	/// Exclude all addresses of `prefix`
+	pub fn remove(&mut self, prefix: &S) {
+		if let Some(node) = self.root.take() {
+			self.root = node.remove(prefix, &mut self.removed);
+		}
+	}
+
+	/// Number of definitions `compress` returns
+	pub fn compress_len(&self, invert: bool) -> usize {
+		match self.root {
+			None => if invert { 1 } else { 0 },
+			Some(ref node) => {
+				let (pos, neg) = node.branch_counts(0);
+				if invert { neg } else { pos }
+			},
+		}
+	}
+
+	/// Same as `Definition::compress` on a `RadixSet` with the same
+	/// addresses (the result is the same)
+	///
+	/// Walks the complete tree; use `changes` to only get the
+	/// differences to a previously generated list.
+	pub fn compress(&self, invert: bool) -> Vec<Definition<S>> {
+		let mut list = Vec::new();
+		match self.root {
+			None => if invert {
+				list.push(Definition{
+					prefix: S::null(),
+					label: true,
+				});
+			},
+			Some(ref node) => {
+				node.emit_branch(0, !invert, &mut list);
+				if invert {
+					for def in &mut list {
+						def.label = !def.label;
+					}
+				}
+			},
+		}
+		list
+	}
+	/// Definitions added and deleted since the last call (the first
+	/// call adds all of them)
+	///
+	/// Applying the changes to the previous list gives the same
+	/// definitions as `compress`.  Only the nodes along changed paths
+	/// (and subtrees whose context changed) are visited.  Switching
+	/// `invert` between calls replaces the complete list.
+	pub fn changes(&mut self, invert: bool) -> Changes<S> {
+		let mut deleted = mem::take(&mut self.removed);
+		let mut added = Vec::new();
+		if let Some(old_invert) = self.emitted_invert {
+			if old_invert != invert {
+				if let Some(ref mut node) = self.root {
+					node.forget(&mut deleted);
+				}
+			}
+			if old_invert {
+				flip(&mut deleted);
+			}
+		}
+		if let Some(ref mut node) = self.root {
+			// `forget` above cleared the state for a changed `invert`,
+			// i.e. this only deletes definitions with the new `invert`
+			let mut removed = Vec::new();
+			node.sync(0, !invert, &mut removed, &mut added);
+			if invert {
+				flip(&mut removed);
+				flip(&mut added);
+			}
+			deleted.append(&mut removed);
+		}
+		let emit_all = invert && self.root.is_none();
+		if emit_all != self.emitted_all {
+			let all = Definition{
+				prefix: S::null(),
+				label: true,
+			};
+			if emit_all { added.push(all) } else { deleted.push(all) }
+		}
+		self.emitted_all = emit_all;
+		self.emitted_invert = Some(invert);
+
+		// drop definitions which got deleted and added again
+		added.sort_by(update::cmp_definitions);
+		deleted.sort_by(update::cmp_definitions);
+		let (mut a, mut d) = (added.into_iter().peekable(), deleted.into_iter().peekable());
+		let mut changes = Changes{
+			added: Vec::new(),
+			deleted: Vec::new(),
+		};
+		loop {
+			let order = match (a.peek(), d.peek()) {
+				(None, None) => break,
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(Some(x), Some(y)) => update::cmp_definitions(x, y),
+			};
+			match order {
+				Ordering::Less => changes.added.extend(a.next()),
+				Ordering::Greater => changes.deleted.extend(d.next()),
+				Ordering::Equal => {
+					a.next();
+					d.next();
+				},
+			}
+		}
+		changes
+	}
+}
@@ -0,0 +1,623 @@
+//! read lists of CIDR ranges
+use bitstring::BitString;
+use bitstring_trees::set::RadixSet;
+use cidr::{AnyIpCidr,Cidr,Ipv4Cidr};
+use family::Ipv4Embedding;
+use prefix::IpPrefix;
+use range;
+use set_def::Definition;
+use set_ops;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self,BufRead,BufReader};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::str::{self,FromStr};
+
+/// Whether a line should be ignored (empty lines and comments starting
+/// with `#`).
+pub fn is_ignored_line(line: &str) -> bool {
+	line.is_empty() || line.as_bytes()[0] == b'#'
+}
+
+/// Parses a single line; returns `None` for empty lines and comments.
+///
+/// `source` and `line_no` are only used to locate errors.
+pub fn parse_line<C>(source: &str, line_no: usize, line: &str) -> Option<Result<C, ParseError>>
+where
+	C: FromStr,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	parse_line_with(source, line_no, line, |text| {
+		C::from_str(text).map_err(|e| format!("{:?}", e))
+	})
+}
+
+/// Parses a single line with a custom parser (which gets the line
+/// without surrounding whitespace); returns `None` for empty lines
+/// and comments.
+///
+/// `source` and `line_no` are only used to locate errors.
+pub fn parse_line_with<T, F>(source: &str, line_no: usize, line: &str, parse: F) -> Option<Result<T, ParseError>>
+where
+	F: FnOnce(&str) -> Result<T, String>,
+{
+	let text = line.trim();
+	if is_ignored_line(text) {
+		return None;
+	}
+	Some(parse(text).map_err(|message| ParseError{
+		source: source.to_string(),
+		line: line_no,
+		column: line.len() - line.trim_start().len() + 1,
+		text: text.to_string(),
+		message,
+	}))
+}
+
+/// How to handle CIDR ranges with host bits set (like
+/// `192.168.1.7/24`)
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum HostBitsPolicy {
+	/// treat as invalid line
+	Reject,
+	/// use the network containing the address (`192.168.1.0/24`)
+	Truncate,
+	/// use only the single address (`192.168.1.7/32`)
+	Host,
+}
+
+/// A single entry in a list of CIDR ranges
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub enum Entry<C> {
+	/// a CIDR range
+	Prefix(C),
+	/// a CIDR range with host bits set, adjusted according to the
+	/// `HostBitsPolicy`
+	HostBits(C),
+	/// an inclusive address range `FIRST-LAST`, split into the minimal
+	/// list of prefixes covering it
+	Range(Vec<C>),
+}
+
+// parse `ADDRESS/LENGTH` with host bits set
+fn parse_host_bits<C>(text: &str, policy: HostBitsPolicy) -> Option<C>
+where
+	C: IpPrefix+FromStr,
+{
+	let pos = text.rfind('/')?;
+	let mut host = C::from_str(&text[..pos]).ok()?;
+	let len = usize::from_str(&text[pos+1..]).ok()?;
+	if !host.is_host() || len > host.prefix_address_length()? {
+		return None;
+	}
+	match policy {
+		HostBitsPolicy::Reject => None,
+		HostBitsPolicy::Truncate => {
+			host.clip(C::FAMILY_BITS + len);
+			Some(host)
+		},
+		HostBitsPolicy::Host => Some(host),
+	}
+}
+
+/// Parses either a CIDR range or an inclusive address range
+/// `FIRST-LAST`.
+pub fn parse_entry<C>(text: &str, host_bits: HostBitsPolicy) -> Result<Entry<C>, String>
+where
+	C: IpPrefix+FromStr,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	if text.contains('-') {
+		return range::parse_range(text).map(Entry::Range).map_err(|e| format!("{:?}", e));
+	}
+	match C::from_str(text) {
+		Ok(c) => Ok(Entry::Prefix(c)),
+		Err(e) => match parse_host_bits(text, host_bits) {
+			Some(c) => Ok(Entry::HostBits(c)),
+			None => Err(format!("{:?}", e)),
+		},
+	}
+}
+
+/// How to normalise IPv4 addresses embedded in IPv6 ranges
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum EmbeddedIpv4Policy {
+	/// keep all ranges unchanged
+	Keep,
+	/// replace IPv4-mapped IPv6 ranges (`::ffff:0:0/96`), and
+	/// optionally 6to4, NAT64 and IPv4-compatible ranges, by the
+	/// embedded IPv4 range
+	Fold {
+		/// also fold 6to4 ranges (`2002::/16`)
+		six_to_four: bool,
+		/// also fold NAT64 ranges (`64:ff9b::/96`)
+		nat64: bool,
+		/// also fold IPv4-compatible ranges (`::/96`)
+		compatible: bool,
+	},
+	/// replace IPv4 ranges by the IPv4-mapped IPv6 range
+	Lift,
+}
+
+impl EmbeddedIpv4Policy {
+	/// Normalises a single prefix (see `Ipv4Embedding`)
+	pub fn normalise(self, prefix: AnyIpCidr) -> AnyIpCidr {
+		match (self, &prefix) {
+			(EmbeddedIpv4Policy::Fold{six_to_four, nat64, compatible}, AnyIpCidr::V6(p)) => {
+				let embeddings = [
+					(true, Ipv4Embedding::Mapped),
+					(six_to_four, Ipv4Embedding::SixToFour),
+					(nat64, Ipv4Embedding::Nat64),
+					(compatible, Ipv4Embedding::Compatible),
+				];
+				for &(enabled, embedding) in &embeddings {
+					if !enabled {
+						continue;
+					}
+					if let Some(ipv4) = embedding.fold(p) {
+						return AnyIpCidr::V4(ipv4);
+					}
+				}
+			},
+			(EmbeddedIpv4Policy::Lift, AnyIpCidr::V4(p)) => return AnyIpCidr::V6(Ipv4Embedding::Mapped.lift(p)),
+			_ => (),
+		}
+		prefix
+	}
+}
+
+// `parse_entry` with the policy for embedded IPv4 addresses applied
+fn parse_normalised_entry<C>(text: &str, host_bits: HostBitsPolicy, embedded_ipv4: EmbeddedIpv4Policy) -> Result<Entry<C>, String>
+where
+	C: IpPrefix+FromStr,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	if EmbeddedIpv4Policy::Keep == embedded_ipv4 {
+		return parse_entry(text, host_bits);
+	}
+	let convert = |prefix: AnyIpCidr| {
+		C::from_any(embedded_ipv4.normalise(prefix)).ok_or_else(|| "unexpected address family".to_string())
+	};
+	Ok(match parse_entry::<AnyIpCidr>(text, host_bits)? {
+		Entry::Prefix(p) => Entry::Prefix(convert(p)?),
+		Entry::HostBits(p) => Entry::HostBits(convert(p)?),
+		Entry::Range(list) => Entry::Range(list.into_iter().map(convert).collect::<Result<_, _>>()?),
+	})
+}
+
+/// A line which couldn't be parsed.
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct ParseError {
+	/// name of the input source (file name, or `<stdin>`)
+	pub source: String,
+	/// line number (starting at 1)
+	pub line: usize,
+	/// column (starting at 1) of the offending text
+	pub column: usize,
+	/// the offending text (without surrounding whitespace)
+	pub text: String,
+	/// description of the problem
+	pub message: String,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}:{}: {}: {:?}", self.source, self.line, self.column, self.message, self.text)
+	}
+}
+
+impl Error for ParseError {
+}
+
+/// Error returned when reading a list of CIDR ranges
+#[derive(Debug)]
+pub enum ReadError {
+	/// Opening the source failed
+	Open {
+		/// name of the input source (file name)
+		source: String,
+		/// underlying error
+		error: io::Error,
+	},
+	/// Reading from the source failed in the given line
+	Io {
+		/// name of the input source (file name, or `<stdin>`)
+		source: String,
+		/// line number (starting at 1)
+		line: usize,
+		/// underlying error
+		error: io::Error,
+	},
+	/// A line couldn't be parsed (only in strict mode)
+	Parse(ParseError),
+}
+
+impl fmt::Display for ReadError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ReadError::Open{ref source, ref error} => {
+				write!(f, "{}: open failed: {}", source, error)
+			},
+			ReadError::Io{ref source, line, ref error} => {
+				write!(f, "{}:{}: read error: {}", source, line, error)
+			},
+			ReadError::Parse(ref e) => fmt::Display::fmt(e, f),
+		}
+	}
+}
+
+impl Error for ReadError {
+	fn source(&self) -> Option<&(dyn Error+'static)> {
+		match *self {
+			ReadError::Open{ref error, ..} => Some(error),
+			ReadError::Io{ref error, ..} => Some(error),
+			ReadError::Parse(ref e) => Some(e),
+		}
+	}
+}
+
+impl From<ParseError> for ReadError {
+	fn from(e: ParseError) -> Self {
+		ReadError::Parse(e)
+	}
+}
+
+/// How to handle lines which can't be parsed
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum InvalidLinePolicy {
+	/// abort reading with an error
+	Strict,
+	/// skip the line (the error is remembered in `SetReader::skipped`)
+	Skip,
+}
+
+/// Format of the input lines
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum InputFormat {
+	/// one CIDR range or inclusive address range `FIRST-LAST` per line;
+	/// the set is the union of all ranges
+	Prefixes,
+	/// one definition (`include PREFIX` or `exclude PREFIX`) per line;
+	/// the set is the result of `Definition::evaluate` on the list
+	Definitions,
+}
+
+/// Options for `SetReader`
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub struct ReadOptions {
+	/// how to handle lines which can't be parsed
+	pub invalid_lines: InvalidLinePolicy,
+	/// format of the input lines
+	pub format: InputFormat,
+	/// how to handle CIDR ranges with host bits set (only used for
+	/// `InputFormat::Prefixes`)
+	pub host_bits: HostBitsPolicy,
+	/// how to normalise IPv4 addresses embedded in IPv6 ranges (only
+	/// used for `InputFormat::Prefixes`)
+	pub embedded_ipv4: EmbeddedIpv4Policy,
+}
+
+impl Default for ReadOptions {
+	fn default() -> Self {
+		ReadOptions{
+			invalid_lines: InvalidLinePolicy::Strict,
+			format: InputFormat::Prefixes,
+			host_bits: HostBitsPolicy::Reject,
+			embedded_ipv4: EmbeddedIpv4Policy::Keep,
+		}
+	}
+}
+
+/// Progress of a `SetReader` (over all sources)
+#[derive(Clone,Copy,PartialEq,Eq,Debug,Default)]
+pub struct ReadProgress {
+	/// number of lines read
+	pub lines: u64,
+	/// number of bytes read
+	pub bytes: u64,
+}
+
+// reads lines into a reused buffer; returns `None` at the end
+fn next_line<'a, R: BufRead>(source: &str, line_no: usize, reader: &mut R, buf: &'a mut Vec<u8>) -> Result<Option<&'a [u8]>, ReadError> {
+	buf.clear();
+	match reader.read_until(b'\n', buf) {
+		Ok(0) => Ok(None),
+		Ok(_) => Ok(Some(buf)),
+		Err(error) => Err(ReadError::Io{
+			source: source.to_string(),
+			line: line_no,
+			error,
+		}),
+	}
+}
+
+// decodes a line; invalid UTF-8 is a parse error (like any other
+// garbage in the line)
+fn line_str<'a>(source: &str, line_no: usize, line: &'a [u8]) -> Result<&'a str, ParseError> {
+	str::from_utf8(line).map_err(|e| {
+		let text = String::from_utf8_lossy(line);
+		let column = text.len() - text.trim_start().len() + 1;
+		ParseError{
+			source: source.to_string(),
+			line: line_no,
+			column,
+			text: text.trim().to_string(),
+			message: format!("invalid UTF-8 after {} bytes", e.valid_up_to()),
+		}
+	})
+}
+
+// parse a decimal number without leading zeros and at most `max`
+fn parse_decimal(digits: &[u8], max: u32) -> Option<u32> {
+	if digits.is_empty() || digits.len() > 3 || (digits.len() > 1 && b'0' == digits[0]) {
+		return None;
+	}
+	let mut value = 0;
+	for &d in digits {
+		if !d.is_ascii_digit() {
+			return None;
+		}
+		value = value * 10 + u32::from(d - b'0');
+	}
+	if value > max { None } else { Some(value) }
+}
+
+// Fast path for the most common lines: parses plain IPv4 CIDR ranges
+// (`A.B.C.D/LEN` or `A.B.C.D`) without host bits directly from the
+// bytes.  Returns `None` for everything else (including invalid
+// lines); these go through the generic (`FromStr`) parsers.
+fn parse_ipv4_bytes(text: &[u8]) -> Option<Ipv4Cidr> {
+	let (addr, len) = match text.iter().position(|&b| b'/' == b) {
+		Some(pos) => (&text[..pos], parse_decimal(&text[pos+1..], 32)? as u8),
+		None => (text, 32),
+	};
+	let mut octets = [0u8; 4];
+	let mut parts = addr.split(|&b| b'.' == b);
+	for octet in &mut octets {
+		*octet = parse_decimal(parts.next()?, 255)? as u8;
+	}
+	if parts.next().is_some() {
+		return None;
+	}
+	Ipv4Cidr::new(Ipv4Addr::from(octets), len).ok()
+}
+
+type ProgressCallback = Box<dyn FnMut(&ReadProgress)>;
+
+/// Builds a set from one CIDR range, address range (`FIRST-LAST`) or
+/// definition per line; empty lines and comments starting with `#` are
+/// ignored, as is whitespace around the entries.
+///
+/// Definition lists are evaluated per source; the set is the union of
+/// all sources.
+///
+/// Lines are read into a reused buffer (no allocation per line), and
+/// plain IPv4 CIDR ranges are parsed directly from the bytes.
+pub struct SetReader<C: BitString> {
+	set: RadixSet<C>,
+	options: ReadOptions,
+	skipped: Vec<ParseError>,
+	host_bits_adjusted: usize,
+	entries: usize,
+	progress: ReadProgress,
+	on_progress: Option<(u64, ProgressCallback)>,
+}
+
+impl<C> SetReader<C>
+where
+	C: IpPrefix+FromStr,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	/// New reader for CIDR ranges (starting with an empty set)
+	pub fn new(policy: InvalidLinePolicy) -> Self {
+		Self::with_options(ReadOptions{
+			invalid_lines: policy,
+			..Default::default()
+		})
+	}
+
+	/// New reader (starting with an empty set)
+	pub fn with_options(options: ReadOptions) -> Self {
+		SetReader{
+			set: RadixSet::default(),
+			options,
+			skipped: Vec::new(),
+			host_bits_adjusted: 0,
+			entries: 0,
+			progress: ReadProgress::default(),
+			on_progress: None,
+		}
+	}
+
+	// remember or return error depending on policy
+	fn invalid_line(&mut self, err: ParseError) -> Result<(), ReadError> {
+		match self.options.invalid_lines {
+			InvalidLinePolicy::Strict => Err(err.into()),
+			InvalidLinePolicy::Skip => {
+				self.skipped.push(err);
+				Ok(())
+			},
+		}
+	}
+
+	/// Read all lines from `reader`; `source` is used to locate errors.
+	pub fn read<R: BufRead>(&mut self, source: &str, mut reader: R) -> Result<(), ReadError> {
+		let mut defs = Vec::new();
+		let mut buf = Vec::new();
+		let mut line_no = 0;
+		while let Some(bytes) = next_line(source, line_no + 1, &mut reader, &mut buf)? {
+			line_no += 1;
+			self.progress.lines += 1;
+			self.progress.bytes += bytes.len() as u64;
+			if let Some((every, ref mut callback)) = self.on_progress {
+				if self.progress.lines.is_multiple_of(every) {
+					callback(&self.progress);
+				}
+			}
+			if InputFormat::Prefixes == self.options.format {
+				let fast = parse_ipv4_bytes(bytes.trim_ascii())
+					.and_then(|c| C::from_any(self.options.embedded_ipv4.normalise(AnyIpCidr::V4(c))));
+				if let Some(c) = fast {
+					self.entries += 1;
+					self.set.insert(c);
+					continue;
+				}
+			}
+			let l = match line_str(source, line_no, bytes) {
+				Ok(l) => l,
+				Err(err) => {
+					self.invalid_line(err)?;
+					continue;
+				},
+			};
+			match self.options.format {
+				InputFormat::Prefixes => match parse_line_with(source, line_no, l, |text| parse_normalised_entry(text, self.options.host_bits, self.options.embedded_ipv4)) {
+					None => (),
+					Some(Ok(Entry::Prefix(c))) => {
+						self.entries += 1;
+						self.set.insert(c);
+					},
+					Some(Ok(Entry::HostBits(c))) => {
+						self.entries += 1;
+						self.host_bits_adjusted += 1;
+						self.set.insert(c);
+					},
+					Some(Ok(Entry::Range(list))) => {
+						self.entries += 1;
+						for c in list {
+							self.set.insert(c);
+						}
+					},
+					Some(Err(err)) => self.invalid_line(err)?,
+				},
+				InputFormat::Definitions => match parse_line::<Definition<C>>(source, line_no, l) {
+					None => (),
+					Some(Ok(def)) => {
+						self.entries += 1;
+						defs.push(def);
+					},
+					Some(Err(err)) => self.invalid_line(err)?,
+				},
+			}
+		}
+		if !defs.is_empty() {
+			let evaluated = Definition::evaluate(&defs);
+			self.set = set_ops::union(&self.set, &evaluated);
+		}
+		Ok(())
+	}
+
+	/// Read all lines from the file at `path`.
+	pub fn read_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ReadError> {
+		let path = path.as_ref();
+		let source = path.display().to_string();
+		let file = match File::open(path) {
+			Ok(f) => f,
+			Err(e) => return Err(ReadError::Open{
+				source,
+				error: e,
+			}),
+		};
+		self.read(&source, BufReader::new(file))
+	}
+
+	/// Lines skipped so far (only with `InvalidLinePolicy::Skip`)
+	pub fn skipped(&self) -> &[ParseError] {
+		&self.skipped
+	}
+
+	/// Number of CIDR ranges with host bits set adjusted so far
+	/// according to the `HostBitsPolicy`
+	pub fn host_bits_adjusted(&self) -> usize {
+		self.host_bits_adjusted
+	}
+
+	/// Number of entries (CIDR ranges, address ranges or definitions)
+	/// read so far
+	pub fn entries(&self) -> usize {
+		self.entries
+	}
+
+	/// Lines and bytes read so far
+	pub fn progress(&self) -> ReadProgress {
+		self.progress
+	}
+
+	/// Calls `callback` after every `every` lines read (counted over all
+	/// sources)
+	pub fn set_progress<F>(&mut self, every: u64, callback: F)
+	where
+		F: FnMut(&ReadProgress)+'static,
+	{
+		assert!(every > 0, "progress interval must be positive");
+		self.on_progress = Some((every, Box::new(callback)));
+	}
+
+	/// The set built so far
+	pub fn set(&self) -> &RadixSet<C> {
+		&self.set
+	}
+
+	/// Finish reading and return the set
+	pub fn into_set(self) -> RadixSet<C> {
+		self.set
+	}
+}
+
+/// Reads one CIDR range per line and builds a set from them; empty
+/// lines and comments starting with `#` are ignored.
+///
+/// Fails on the first line that can't be parsed.
+pub fn read<C, R>(reader: R) -> Result<RadixSet<C>, ReadError>
+where
+	C: IpPrefix+FromStr,
+	<C as FromStr>::Err: fmt::Debug,
+	R: BufRead,
+{
+	let mut r = SetReader::new(InvalidLinePolicy::Strict);
+	r.read("<input>", reader)?;
+	Ok(r.into_set())
+}
+
+/// Reads one definition per line and returns the list as is (i.e. not
+/// evaluated); empty lines and comments starting with `#` are ignored.
+///
+/// Fails on the first line that can't be parsed.
+pub fn read_definitions<C, R>(source: &str, reader: R) -> Result<Vec<Definition<C>>, ReadError>
+where
+	C: IpPrefix+FromStr,
+	<C as FromStr>::Err: fmt::Debug,
+	R: BufRead,
+{
+	read_definitions_with(source, reader, InvalidLinePolicy::Strict, &mut Vec::new())
+}
+
+/// Same as `read_definitions`, but handles lines which can't be parsed
+/// according to `policy`; skipped lines are appended to `skipped`.
+pub fn read_definitions_with<C, R>(source: &str, mut reader: R, policy: InvalidLinePolicy, skipped: &mut Vec<ParseError>) -> Result<Vec<Definition<C>>, ReadError>
+where
+	C: IpPrefix+FromStr,
+	<C as FromStr>::Err: fmt::Debug,
+	R: BufRead,
+{
+	let mut defs = Vec::new();
+	let mut buf = Vec::new();
+	let mut line_no = 0;
+	while let Some(bytes) = next_line(source, line_no + 1, &mut reader, &mut buf)? {
+		line_no += 1;
+		let parsed = line_str(source, line_no, bytes)
+			.map(|l| parse_line::<Definition<C>>(source, line_no, l))
+			.unwrap_or_else(|err| Some(Err(err)));
+		match parsed {
+			None => (),
+			Some(Ok(def)) => defs.push(def),
+			Some(Err(err)) => match policy {
+				InvalidLinePolicy::Strict => return Err(err.into()),
+				InvalidLinePolicy::Skip => skipped.push(err),
+			},
+		}
+	}
+	Ok(defs)
+}
@@ -0,0 +1,36 @@
+//! Converts (positive) CIDR lists into minimal lists of positive and
+//! negative definitions.
+//!
+//! The command line tool `compress-cidr` is a thin wrapper around this
+//! library; see `Definition::compress` and `Definition::complete` for
+//! the actual algorithms, and the `input` module to build a `RadixSet`
+//! from a list of CIDR ranges.
+
+extern crate num_traits;
+
+pub extern crate cidr;
+pub extern crate bitstring;
+pub extern crate bitstring_trees;
+
+pub mod deaggregate;
+pub mod diff;
+pub mod family;
+pub mod incremental;
+pub mod input;
+pub mod limits;
+pub mod lossy;
+pub mod prefix;
+pub mod range;
+pub mod set_def;
+pub mod set_ops;
+pub mod stats;
+pub mod update;
+pub mod write_lines;
+
+#[cfg(test)]
+mod tests;
+
+pub use bitstring_trees::set::RadixSet;
+pub use prefix::IpPrefix;
+pub use set_def::Definition;
+pub use write_lines::WriteLinesIter;
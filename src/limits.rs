@@ -0,0 +1,336 @@
+//! limits on the prefix length of generated definitions
+use bitstring::BitString;
+use bitstring_trees::set::RadixSet;
+use prefix::IpPrefix;
+use set_def::Definition;
+use set_ops;
+use std::error::Error;
+use std::fmt;
+
+/// How to handle prefixes in the set longer than the maximum length
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum TooLongPolicy {
+	/// fail with `TooLong`
+	Fail,
+	/// use the network of the maximum length containing the prefix
+	/// instead; this includes additional addresses
+	RoundOutward,
+}
+
+/// Network length bounds for the prefixes of one address family
+#[derive(Clone,Copy,PartialEq,Eq,Debug,Default)]
+pub struct LengthBounds {
+	/// minimum network length; shorter definitions are split
+	pub min: usize,
+	/// maximum network length (if any)
+	pub max: Option<usize>,
+}
+
+/// Limits on the network length of generated definitions
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub struct LengthLimits {
+	/// bounds for IPv4 prefixes
+	pub ipv4: LengthBounds,
+	/// bounds for IPv6 prefixes
+	pub ipv6: LengthBounds,
+	/// how to handle prefixes longer than the maximum
+	pub too_long: TooLongPolicy,
+	/// maximum number of definitions after splitting short definitions
+	/// (`u128::MAX` for no limit)
+	pub split_limit: u128,
+}
+
+impl Default for LengthLimits {
+	fn default() -> Self {
+		LengthLimits{
+			ipv4: LengthBounds::default(),
+			ipv6: LengthBounds::default(),
+			too_long: TooLongPolicy::Fail,
+			split_limit: u128::MAX,
+		}
+	}
+}
+
+impl LengthLimits {
+	/// Same bounds for both families
+	pub fn new(bounds: LengthBounds) -> Self {
+		LengthLimits{
+			ipv4: bounds,
+			ipv6: bounds,
+			..Default::default()
+		}
+	}
+
+	/// Bounds for prefixes with the given address length (32 for IPv4,
+	/// 128 for IPv6)
+	pub fn bounds(&self, address_length: usize) -> &LengthBounds {
+		if 32 == address_length {
+			&self.ipv4
+		} else {
+			&self.ipv6
+		}
+	}
+
+	// bounds of the family of `prefix`; `None` for `AnyIpCidr::Any`
+	fn prefix_bounds<S: IpPrefix>(&self, prefix: &S) -> Option<&LengthBounds> {
+		prefix.prefix_address_length().map(|a| self.bounds(a))
+	}
+}
+
+/// Result of `Definition::limit_lengths`
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct Limited<S: BitString> {
+	/// the definitions
+	pub definitions: Vec<Definition<S>>,
+	/// prefixes of the (possibly inverted) set which were too long and
+	/// got rounded outward
+	pub rounded: Vec<S>,
+	/// number of addresses included by rounding outward; saturates at
+	/// `u128::MAX`
+	pub over_covered: u128,
+}
+
+/// Error returned if the (possibly inverted) set contains a prefix
+/// longer than the maximum length
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct TooLong<S> {
+	/// the (first) prefix which is too long
+	pub prefix: S,
+	/// the maximum network length
+	pub max: usize,
+}
+
+impl<S: fmt::Display> fmt::Display for TooLong<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} is longer than /{}", self.prefix, self.max)
+	}
+}
+
+impl<S: fmt::Debug+fmt::Display> Error for TooLong<S> {
+}
+
+/// Error returned if splitting short definitions would generate more
+/// than `LengthLimits::split_limit` definitions
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub struct TooManyRules {
+	/// (upper bound of the) number of definitions after splitting;
+	/// saturates at `u128::MAX`
+	pub count: u128,
+	/// the limit
+	pub limit: u128,
+}
+
+impl fmt::Display for TooManyRules {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "splitting short rules would generate {} rules (limit {})", self.count, self.limit)
+	}
+}
+
+impl Error for TooManyRules {
+}
+
+/// Error returned by `Definition::limit_lengths`
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub enum LimitError<S> {
+	/// the (possibly inverted) set contains a prefix longer than the
+	/// maximum length
+	TooLong(TooLong<S>),
+	/// splitting short definitions would exceed the limit
+	TooManyRules(TooManyRules),
+}
+
+impl<S> From<TooLong<S>> for LimitError<S> {
+	fn from(e: TooLong<S>) -> Self {
+		LimitError::TooLong(e)
+	}
+}
+
+impl<S> From<TooManyRules> for LimitError<S> {
+	fn from(e: TooManyRules) -> Self {
+		LimitError::TooManyRules(e)
+	}
+}
+
+impl<S: fmt::Display> fmt::Display for LimitError<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			LimitError::TooLong(ref e) => fmt::Display::fmt(e, f),
+			LimitError::TooManyRules(ref e) => fmt::Display::fmt(e, f),
+		}
+	}
+}
+
+impl<S: fmt::Debug+fmt::Display> Error for LimitError<S> {
+}
+
+// the maximum length of the family of `prefix` if the prefix is
+// longer
+fn too_long<S: IpPrefix>(prefix: &S, limits: &LengthLimits) -> Option<usize> {
+	let max = limits.prefix_bounds(prefix)?.max?;
+	if prefix.prefix_network_length()? > max { Some(max) } else { None }
+}
+
+// split `prefix` until the network length reaches the minimum of its
+// family (or the prefix is a single address)
+fn split<S: IpPrefix>(prefix: S, limits: &LengthLimits, list: &mut Vec<S>) {
+	let done = match (prefix.prefix_network_length(), limits.prefix_bounds(&prefix)) {
+		(Some(l), Some(bounds)) => l >= bounds.min || prefix.is_host(),
+		_ => false,
+	};
+	if done {
+		list.push(prefix);
+		return;
+	}
+	let mut right = prefix.clone();
+	right.append(true);
+	let mut left = prefix;
+	left.append(false);
+	split(left, limits, list);
+	split(right, limits, list);
+}
+
+// number of prefixes `split` generates; saturates at `u128::MAX`
+fn split_count<S: IpPrefix>(prefix: &S, limits: &LengthLimits) -> u128 {
+	match (prefix.prefix_network_length(), prefix.prefix_address_length()) {
+		(Some(l), Some(a)) => match limits.bounds(a).min.min(a).saturating_sub(l) {
+			bits if bits < 128 => 1 << bits,
+			_ => u128::MAX,
+		},
+		_ => {
+			let mut right = prefix.clone();
+			right.append(true);
+			let mut left = prefix.clone();
+			left.append(false);
+			split_count(&left, limits).saturating_add(split_count(&right, limits))
+		},
+	}
+}
+
+/// Replaces definitions shorter than the minimum length of their family
+/// with definitions of the minimum length (longest matching prefix
+/// semantics stay the same).
+///
+/// Excluding parts are only needed to keep a complete coverage of the
+/// address space (see `Definition::complete`); without
+/// `keep_excludes` they are dropped, as addresses not covered are
+/// excluded anyway.
+///
+/// The result is sorted lexicographically.  Splitting short
+/// definitions can lead to a huge number of definitions: each
+/// definition of length `l` is split into `2^(min - l)` definitions.
+/// If the result would contain more than `limits.split_limit`
+/// definitions (counted before removing split definitions replaced by
+/// remaining ones), an error is returned instead; nothing is split in
+/// that case.
+pub fn split_short<S: IpPrefix>(defs: Vec<Definition<S>>, limits: &LengthLimits, keep_excludes: bool) -> Result<Vec<Definition<S>>, TooManyRules> {
+	let is_short = |def: &Definition<S>| match (def.prefix.prefix_network_length(), limits.prefix_bounds(&def.prefix)) {
+		(Some(l), Some(bounds)) => l < bounds.min && !def.prefix.is_host(),
+		_ => true,
+	};
+	if (0 == limits.ipv4.min && 0 == limits.ipv6.min) || !defs.iter().any(&is_short) {
+		return Ok(defs);
+	}
+	let (short, mut list) : (Vec<_>, Vec<_>) = defs.into_iter().partition(&is_short);
+	// addresses included by the short definitions, and all addresses
+	// covered by them
+	let included = Definition::evaluate(&short);
+	let mut covered = RadixSet::default();
+	for def in &short {
+		covered.insert(def.prefix.clone());
+	}
+	let mut count = list.len() as u128;
+	set_ops::walk(&included, &covered, |prefix, include, covered| {
+		if covered && (include || keep_excludes) {
+			count = count.saturating_add(split_count(prefix, limits));
+		}
+	});
+	if count > limits.split_limit {
+		return Err(TooManyRules{
+			count,
+			limit: limits.split_limit,
+		});
+	}
+	set_ops::walk(&included, &covered, |prefix, include, covered| {
+		if covered && (include || keep_excludes) {
+			let mut parts = Vec::new();
+			split(prefix.clone(), limits, &mut parts);
+			list.extend(parts.into_iter().map(|prefix| Definition{
+				prefix,
+				label: include,
+			}));
+		}
+	});
+	// stable sort: remaining definitions override split ones for the
+	// same prefix
+	list.sort_by(|a, b| a.prefix.lexicographic_cmp(&b.prefix));
+	list.dedup_by(|b, a| a.prefix.lexicographic_cmp(&b.prefix).is_eq());
+	Ok(list)
+}
+
+impl<S: IpPrefix> Definition<S> {
+	/// Generates definitions for the (possibly inverted) set with
+	/// `generate` (e.g. `Definition::compress`) respecting the length
+	/// limits.
+	///
+	/// Prefixes of the (possibly inverted) set longer than the maximum
+	/// length of their family are handled according to
+	/// `limits.too_long`; if they are
+	/// rounded outward, `generate` gets the rounded (and already
+	/// inverted) set.  Definitions shorter than the minimum length are
+	/// split (see `split_short`; `keep_excludes` should be set if
+	/// `generate` returns a complete coverage which is needed as such).
+	pub fn limit_lengths<F>(s: &RadixSet<S>, invert: bool, limits: &LengthLimits, keep_excludes: bool, generate: F) -> Result<Limited<S>, LimitError<S>>
+	where
+		F: FnOnce(&RadixSet<S>, bool) -> Vec<Definition<S>>,
+	{
+		let mut rounded = Vec::new();
+		let mut over_covered = 0;
+		let definitions = if limits.ipv4.max.is_none() && limits.ipv6.max.is_none() {
+			generate(s, invert)
+		} else {
+			let inverted;
+			let target = if invert {
+				inverted = set_ops::combine(s, s, |a, _| !a);
+				&inverted
+			} else {
+				s
+			};
+			rounded = target.iter().filter(|p| too_long(*p, limits).is_some()).cloned().collect();
+			if rounded.is_empty() {
+				generate(s, invert)
+			} else if TooLongPolicy::Fail == limits.too_long {
+				let prefix = rounded.swap_remove(0);
+				let max = too_long(&prefix, limits).unwrap();
+				return Err(TooLong{
+					prefix,
+					max,
+				}.into());
+			} else {
+				let mut round_set = target.clone();
+				for prefix in &rounded {
+					let mut network = prefix.clone();
+					network.clip(S::FAMILY_BITS + too_long(prefix, limits).unwrap());
+					round_set.insert(network);
+				}
+				over_covered = set_ops::difference(&round_set, target).iter()
+					.fold(0u128, |sum, p| sum.saturating_add(p.address_count()));
+				generate(&round_set, false)
+			}
+		};
+		Ok(Limited{
+			definitions: split_short(definitions, limits, keep_excludes)?,
+			rounded,
+			over_covered,
+		})
+	}
+
+	/// `compress` respecting the length limits (see `limit_lengths`)
+	pub fn compress_limited(s: &RadixSet<S>, invert: bool, limits: &LengthLimits) -> Result<Limited<S>, LimitError<S>> {
+		Self::limit_lengths(s, invert, limits, false, Self::compress)
+	}
+
+	/// `complete` respecting the length limits (see `limit_lengths`)
+	pub fn complete_limited(s: &RadixSet<S>, invert: bool, limits: &LengthLimits) -> Result<Limited<S>, LimitError<S>> {
+		Self::limit_lengths(s, invert, limits, true, Self::complete)
+	}
+}
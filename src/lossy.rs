@@ -0,0 +1,285 @@
+//! lossy compression: limit the number of rules, but include as few
+//! additional addresses as possible
+use bitstring::BitString;
+use bitstring_trees::set::{RadixSet,Node};
+use prefix::IpPrefix;
+use set_def::Definition;
+use set_ops;
+use std::cmp::min;
+
+/// Result of `Definition::compress_lossy`
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct LossyDefinitions<S: BitString> {
+	/// the definitions (longest matching prefix wins)
+	pub definitions: Vec<Definition<S>>,
+	/// number of addresses included by the definitions but not part of
+	/// the (possibly inverted) set; saturates at `u128::MAX`
+	pub over_covered: u128,
+}
+
+// number of over-covered addresses; `None` if not possible with the
+// given number of rules
+type Cost = Option<u128>;
+
+fn add(a: Cost, b: Cost) -> Cost {
+	match (a, b) {
+		(Some(a), Some(b)) => Some(a.saturating_add(b)),
+		_ => None,
+	}
+}
+
+fn min_cost(a: Cost, b: Cost) -> Cost {
+	match (a, b) {
+		(Some(a), Some(b)) => Some(min(a, b)),
+		(None, b) => b,
+		(a, None) => a,
+	}
+}
+
+// cost lists are indexed by the number of rules available (at most);
+// entries after the end are the same as the last entry
+fn at(list: &[Cost], k: usize) -> Cost {
+	list[min(k, list.len() - 1)]
+}
+
+fn trim(list: &mut Vec<Cost>) {
+	while list.len() > 1 && list[list.len() - 1] == list[list.len() - 2] {
+		list.pop();
+	}
+}
+
+const EXCLUDE: usize = 0;
+const INCLUDE: usize = 1;
+
+// cost lists for a sub tree, depending on whether the region is
+// excluded (`cost[EXCLUDE]`) or included (`cost[INCLUDE]`) by the
+// parent rules
+struct Tables<'a, S: IpPrefix+'a> {
+	node: &'a Node<S>,
+	cost: [Vec<Cost>; 2],
+	children: Option<Box<(Tables<'a, S>, Tables<'a, S>)>>,
+}
+
+// minimal cost for splitting rules between two independent regions
+fn combine(a: &[Cost], b: &[Cost], max_rules: usize) -> Vec<Cost> {
+	let len = min(max_rules + 1, a.len() + b.len() - 1);
+	let mut list = Vec::with_capacity(len);
+	for k in 0..len {
+		let mut best = None;
+		let first = if k >= b.len() { k - (b.len() - 1) } else { 0 };
+		for (i, &cost) in a.iter().enumerate().take(min(k, a.len() - 1) + 1).skip(first) {
+			best = min_cost(best, add(cost, at(b, k - i)));
+		}
+		list.push(best);
+	}
+	trim(&mut list);
+	list
+}
+
+// sizes of the regions next to the path from `from_len` down to the
+// node (top first), and the sum of all sizes from a certain depth on
+fn gaps<S: IpPrefix>(key: &S, from_len: usize) -> Vec<u128> {
+	let mut rest = vec![0u128; key.len() - from_len + 1];
+	for d in (from_len..key.len()).rev() {
+		let mut gap = key.clone();
+		gap.clip(d + 1);
+		gap.flip(d);
+		rest[d - from_len] = rest[d - from_len + 1].saturating_add(gap.address_count());
+	}
+	rest
+}
+
+impl<'a, S: IpPrefix+'a> Tables<'a, S> {
+	fn new(node: &'a Node<S>, max_rules: usize) -> Self {
+		match *node {
+			Node::Leaf(_) => Tables{
+				node,
+				cost: [vec![None, Some(0)], vec![Some(0)]],
+				children: None,
+			},
+			Node::InnerNode(ref inner) => {
+				let from_len = inner.key().len() + 1;
+				let left = Tables::new(inner.left(), max_rules);
+				let right = Tables::new(inner.right(), max_rules);
+				let without_rule = [
+					combine(&left.path_cost(from_len, EXCLUDE, max_rules), &right.path_cost(from_len, EXCLUDE, max_rules), max_rules),
+					combine(&left.path_cost(from_len, INCLUDE, max_rules), &right.path_cost(from_len, INCLUDE, max_rules), max_rules),
+				];
+				let mut cost = [Vec::new(), Vec::new()];
+				for c in 0..2 {
+					// optionally a rule for the node prefix switching the
+					// context
+					let len = min(max_rules + 1, without_rule[c].len().max(without_rule[1 - c].len() + 1));
+					for k in 0..len {
+						let mut best = at(&without_rule[c], k);
+						if k > 0 {
+							best = min_cost(best, at(&without_rule[1 - c], k - 1));
+						}
+						cost[c].push(best);
+					}
+					trim(&mut cost[c]);
+				}
+				Tables{
+					node,
+					cost,
+					children: Some(Box::new((left, right))),
+				}
+			},
+		}
+	}
+
+	// cost list for the region `key[0..from_len]` containing the sub
+	// tree
+	fn path_cost(&self, from_len: usize, context: usize, max_rules: usize) -> Vec<Cost> {
+		let key = self.node.key();
+		let pathlen = key.len() - from_len;
+		if EXCLUDE == context || 0 == pathlen {
+			// no gaps, or gaps already excluded
+			return self.cost[context].clone();
+		}
+		let rest = gaps(key, from_len);
+		let include = &self.cost[INCLUDE];
+		let len = min(max_rules + 1, include.len() + pathlen + 1);
+		let mut list = Vec::with_capacity(len);
+		for k in 0..len {
+			// exclude the `j` largest gaps with one rule each, and keep
+			// the others included
+			let mut best = None;
+			for (j, &remaining) in rest.iter().enumerate().take(min(k, pathlen) + 1) {
+				best = min_cost(best, add(Some(remaining), at(include, k - j)));
+			}
+			// or exclude the complete region with a single rule
+			if k > 0 {
+				best = min_cost(best, at(&self.cost[EXCLUDE], k - 1));
+			}
+			list.push(best);
+		}
+		trim(&mut list);
+		list
+	}
+
+	fn emit_node(&self, context: usize, k: usize, max_rules: usize, list: &mut Vec<Definition<S>>) {
+		let target = at(&self.cost[context], k);
+		match self.children {
+			None => {
+				if EXCLUDE == context {
+					list.push(Definition{
+						prefix: self.node.key().clone(),
+						label: true,
+					});
+				}
+			},
+			Some(ref children) => {
+				let from_len = self.node.key().len() + 1;
+				let (ref left, ref right) = **children;
+				let split = |c: usize, k: usize| -> Option<(usize, Vec<Cost>, Vec<Cost>)> {
+					let l = left.path_cost(from_len, c, max_rules);
+					let r = right.path_cost(from_len, c, max_rules);
+					(0..k + 1)
+						.find(|&i| add(at(&l, i), at(&r, k - i)) == target)
+						.map(|i| (i, l, r))
+				};
+				let (c, k) = match split(context, k) {
+					Some(_) => (context, k),
+					None => {
+						debug_assert!(k > 0);
+						list.push(Definition{
+							prefix: self.node.key().clone(),
+							label: INCLUDE != context,
+						});
+						(1 - context, k - 1)
+					},
+				};
+				let (i, l, r) = split(c, k).expect("inconsistent cost tables");
+				left.emit_path(from_len, c, i, at(&l, i), max_rules, list);
+				right.emit_path(from_len, c, k - i, at(&r, k - i), max_rules, list);
+			},
+		}
+	}
+
+	fn emit_path(&self, from_len: usize, context: usize, k: usize, target: Cost, max_rules: usize, list: &mut Vec<Definition<S>>) {
+		let key = self.node.key();
+		let pathlen = key.len() - from_len;
+		if EXCLUDE == context || 0 == pathlen {
+			self.emit_node(context, k, max_rules, list);
+			return;
+		}
+		let rest = gaps(key, from_len);
+		for (j, &remaining) in rest.iter().enumerate().take(min(k, pathlen) + 1) {
+			if add(Some(remaining), at(&self.cost[INCLUDE], k - j)) == target {
+				let exclude_gap = |d: usize| {
+					let mut gap = key.clone();
+					gap.clip(d + 1);
+					gap.flip(d);
+					Definition{
+						prefix: gap,
+						label: false,
+					}
+				};
+				// keep lexicographic order
+				for d in from_len..from_len + j {
+					if key.get(d) {
+						list.push(exclude_gap(d));
+					}
+				}
+				self.emit_node(INCLUDE, k - j, max_rules, list);
+				for d in (from_len..from_len + j).rev() {
+					if !key.get(d) {
+						list.push(exclude_gap(d));
+					}
+				}
+				return;
+			}
+		}
+		debug_assert!(k > 0);
+		let mut excl = key.clone();
+		excl.clip(from_len);
+		list.push(Definition{
+			prefix: excl,
+			label: false,
+		});
+		self.emit_node(EXCLUDE, k - 1, max_rules, list);
+	}
+}
+
+impl<S: IpPrefix> Definition<S> {
+	/// Returns a list of at most `max_rules` definitions which include
+	/// all addresses of the (possibly inverted) set, and as few other
+	/// addresses as possible (and, with that, as few rules as
+	/// possible).
+	///
+	/// Same semantics as `compress`: the longest matching prefix decides
+	/// whether an address is included, addresses not covered are
+	/// excluded.
+	///
+	/// Returns `None` if `max_rules` is zero and the set isn't empty.
+	pub fn compress_lossy(s: &RadixSet<S>, invert: bool, max_rules: usize) -> Option<LossyDefinitions<S>> {
+		let inverted;
+		let s = if invert {
+			inverted = set_ops::combine(s, s, |a, _| !a);
+			&inverted
+		} else {
+			s
+		};
+		let root = match s.root() {
+			None => return Some(LossyDefinitions{
+				definitions: Vec::new(),
+				over_covered: 0,
+			}),
+			Some(n) => n,
+		};
+
+		let tables = Tables::new(root, max_rules);
+		let cost = &tables.cost[EXCLUDE];
+		let over_covered = at(cost, max_rules)?;
+		// use as few rules as possible
+		let k = (0..max_rules + 1).find(|&k| at(cost, k) == Some(over_covered)).unwrap();
+
+		let mut definitions = Vec::new();
+		tables.emit_path(0, EXCLUDE, k, Some(over_covered), max_rules, &mut definitions);
+		Some(LossyDefinitions{
+			definitions,
+			over_covered,
+		})
+	}
+}
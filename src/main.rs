@@ -1,21 +1,11 @@
-extern crate num_traits;
+extern crate compress_cidr;
 
-extern crate cidr;
-extern crate bitstring;
-extern crate bitstring_trees;
-
-use bitstring_trees::set::RadixSet;
-
-pub mod set_def;
-pub mod write_lines;
-
-#[cfg(test)]
-mod tests;
-
-use write_lines::WriteLinesIter;
+use compress_cidr::{cidr,deaggregate,diff,family,input,limits,set_def,set_ops,stats};
+use compress_cidr::{IpPrefix,RadixSet,WriteLinesIter};
 
 use std::str::FromStr;
 use std::fmt;
+use std::time::Instant;
 
 macro_rules! print_stderr(
 	($($arg:tt)*) => { {
@@ -43,14 +33,177 @@ struct Config {
 	invert: bool,
 	complete: bool,
 	aggregate: bool,
+	skip_invalid: bool,
+	definitions: bool,
+	host_bits: input::HostBitsPolicy,
+	embedded_ipv4: input::EmbeddedIpv4Policy,
+	verify: bool,
+	max_rules: Option<usize>,
+	dont_care: Vec<String>,
+	optimal: bool,
+	compare_optimal: bool,
+	costs: Option<set_def::RuleCosts>,
+	limits: Option<limits::LengthLimits>,
+	deaggregate: Option<usize>,
+	keep_longer: bool,
+	deaggregate_limit: u128,
+	stats: bool,
+	diff: bool,
+	update: Option<String>,
+	progress: bool,
+	per_family: bool,
+	ipv4_output: Option<String>,
+	ipv6_output: Option<String>,
+	#[cfg(feature = "parallel")]
+	threads: usize,
 	protocol: ConfigProtocol,
+	operation: set_ops::SetOperation,
+	lookup: Option<String>,
+	inputs: Vec<String>,
 }
 use std::option::Option;
 
-const NAME: &'static str = env!("CARGO_PKG_NAME");
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const AUTHORS: &'static str = env!("CARGO_PKG_AUTHORS");
-const DESC: &'static str = "Converts (positive) CIDR list into minimal list of positive and negative definitions";
+const NAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
+const DESC: &str = "Converts (positive) CIDR list into minimal list of positive and negative definitions";
+
+fn parse_host_bits(name: &str) -> Option<input::HostBitsPolicy> {
+	match name {
+		"reject" => Some(input::HostBitsPolicy::Reject),
+		"truncate" => Some(input::HostBitsPolicy::Truncate),
+		"host" => Some(input::HostBitsPolicy::Host),
+		_ => None,
+	}
+}
+
+fn embedded_ipv4_policy(fold: bool, six_to_four: bool, nat64: bool, compatible: bool, lift: bool) -> input::EmbeddedIpv4Policy {
+	if fold {
+		input::EmbeddedIpv4Policy::Fold{six_to_four, nat64, compatible}
+	} else if lift {
+		input::EmbeddedIpv4Policy::Lift
+	} else {
+		input::EmbeddedIpv4Policy::Keep
+	}
+}
+
+// parse `LEN=COST`
+fn parse_length_cost(spec: &str) -> Option<(usize, u64)> {
+	let pos = spec.find('=')?;
+	let len = usize::from_str(&spec[..pos]).ok()?;
+	let cost = u64::from_str(&spec[pos+1..]).ok()?;
+	Some((len, cost))
+}
+
+// build cost model from the given options; `None` if none were given
+fn rule_costs(include: Option<u64>, exclude: Option<u64>, length_costs: &[(usize, u64)]) -> Option<set_def::RuleCosts> {
+	if include.is_none() && exclude.is_none() && length_costs.is_empty() {
+		return None;
+	}
+	let mut costs = set_def::RuleCosts::default();
+	costs.include = include.unwrap_or(costs.include);
+	costs.exclude = exclude.unwrap_or(costs.exclude);
+	for &(len, cost) in length_costs {
+		if costs.per_length.len() <= len {
+			costs.per_length.resize(len + 1, 0);
+		}
+		costs.per_length[len] = cost;
+	}
+	Some(costs)
+}
+
+/// length limit options (without `--`); the values passed to
+/// `length_limits` use the same order
+const LENGTH_OPTIONS: [&str; 6] = ["min-length", "max-length", "min-length-v4", "max-length-v4", "min-length-v6", "max-length-v6"];
+
+// build length limits from the given options (see `LENGTH_OPTIONS`);
+// `None` if none were given.  The per family options override the
+// generic ones, and are only allowed if the family is selected.
+fn length_limits(lengths: &[Option<usize>; 6], round_long: bool, split_limit: Option<u128>, ipv4: bool, ipv6: bool) -> Result<Option<limits::LengthLimits>, String> {
+	for &(first, flag, excluded) in &[(4, "-4", ipv4), (2, "-6", ipv6)] {
+		for ndx in first..first + 2 {
+			if excluded && lengths[ndx].is_some() {
+				return Err(format!("--{} can't be combined with {}", LENGTH_OPTIONS[ndx], flag));
+			}
+		}
+	}
+	if round_long && lengths.iter().skip(1).step_by(2).all(Option::is_none) {
+		return Err("--round-long requires --max-length".to_string());
+	}
+	if split_limit.is_some() && lengths.iter().step_by(2).all(Option::is_none) {
+		return Err("--split-limit requires --min-length".to_string());
+	}
+	if lengths.iter().all(Option::is_none) {
+		return Ok(None);
+	}
+	// generic options are checked against the longest address length
+	// in use; shorter addresses simply never reach them
+	let generic_length = if ipv4 { 32 } else { 128 };
+	let bounds = |first: usize, address_length: usize| -> Result<limits::LengthBounds, String> {
+		let mut ndx = [0, 1];
+		let mut len = [None, None];
+		for i in 0..2 {
+			if lengths[first + i].is_some() {
+				ndx[i] = first + i;
+			}
+			len[i] = lengths[ndx[i]];
+			let longest = if ndx[i] < 2 { generic_length } else { address_length };
+			if let Some(l) = len[i].filter(|&l| l > longest) {
+				return Err(format!("--{} {} is longer than the address length /{}", LENGTH_OPTIONS[ndx[i]], l, longest));
+			}
+		}
+		if let [Some(min), Some(max)] = len {
+			if min > max {
+				return Err(format!("--{} {} is longer than --{} {}", LENGTH_OPTIONS[ndx[0]], min, LENGTH_OPTIONS[ndx[1]], max));
+			}
+		}
+		Ok(limits::LengthBounds{
+			min: len[0].unwrap_or(0),
+			max: len[1],
+		})
+	};
+	Ok(Some(limits::LengthLimits{
+		ipv4: if ipv6 { Default::default() } else { bounds(2, 32)? },
+		ipv6: if ipv4 { Default::default() } else { bounds(4, 128)? },
+		too_long: if round_long {
+			limits::TooLongPolicy::RoundOutward
+		} else {
+			limits::TooLongPolicy::Fail
+		},
+		split_limit: split_limit.unwrap_or(DEFAULT_SPLIT_LIMIT),
+	}))
+}
+
+/// default for `--deaggregate-limit`
+const DEFAULT_DEAGGREGATE_LIMIT: u128 = 1 << 20;
+
+/// default for `--split-limit`
+const DEFAULT_SPLIT_LIMIT: u128 = 1 << 20;
+
+// limit on the output size; `0` means no limit
+fn output_limit(n: u64) -> u128 {
+	if 0 == n {
+		u128::MAX
+	} else {
+		n as u128
+	}
+}
+
+// `--threads` default: the number of available CPUs
+#[cfg(feature = "parallel")]
+fn default_threads() -> usize {
+	std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn parse_operation(name: &str) -> Option<set_ops::SetOperation> {
+	match name {
+		"union" => Some(set_ops::SetOperation::Union),
+		"intersection" => Some(set_ops::SetOperation::Intersection),
+		"difference" => Some(set_ops::SetOperation::Difference),
+		"xor" => Some(set_ops::SetOperation::SymmetricDifference),
+		_ => None,
+	}
+}
 
 #[cfg(not(feature = "clap"))]
 extern crate getopts;
@@ -58,7 +211,7 @@ extern crate getopts;
 #[cfg(not(feature = "clap"))]
 fn print_usage(program: &str, opts: getopts::Options) {
 	print_stderr!("{} {}\n{}\n{}\n\n", NAME, VERSION, AUTHORS, DESC);
-	let brief = format!("Usage: {} [-i] [-a|-c] [-4|-6]", program);
+	let brief = format!("Usage: {} [-i] [-a|-c] [-4|-6] [-d] [--host-bits POLICY] [--fold-mapped [--fold-6to4] [--fold-nat64] [--fold-compatible]|--lift-mapped] [--verify|--max-rules N] [--dont-care FILE] [--optimal|--compare-optimal] [--include-cost N] [--exclude-cost N] [--length-cost LEN=N] [--min-length[-v4|-v6] N [--split-limit N]] [--max-length[-v4|-v6] N [--round-long]] [--deaggregate N [--keep-longer] [--deaggregate-limit N]] [--stats] [--diff OLD NEW] [--update FILE] [--progress] [--per-family [--ipv4-output FILE] [--ipv6-output FILE]] [--strict|-s] [-o OP] [-l FILE] [FILE...]", program);
 	print_stderr!("{}", opts.usage(&brief));
 }
 
@@ -76,6 +229,45 @@ fn get_config() -> Option<Config> {
 	opts.optflag("c", "complete", "Complete covering list of ranges");
 	opts.optflag("a", "aggregate", "Aggregate including ranges");
 	opts.optflag("i", "invert", "Invert input list");
+	opts.optopt("o", "operation", "Combine input files with OP: union (default), intersection, difference or xor", "OP");
+	opts.optopt("l", "lookup", "Look up prefixes from FILE ('-' for stdin) in the generated rules (with -d: in the definition list)", "FILE");
+	opts.optflag("d", "definitions", "Input files contain include/exclude definitions");
+	opts.optopt("", "host-bits", "Handle CIDR ranges with host bits set: reject (default), truncate (to the network) or host (single address)", "POLICY");
+	opts.optflag("", "fold-mapped", "Replace IPv4-mapped IPv6 ranges (::ffff:0:0/96) in the input by the IPv4 ranges");
+	opts.optflag("", "fold-6to4", "With --fold-mapped also replace 6to4 ranges (2002::/16, up to /48) by the IPv4 ranges");
+	opts.optflag("", "fold-nat64", "With --fold-mapped also replace NAT64 ranges (64:ff9b::/96) by the IPv4 ranges");
+	opts.optflag("", "fold-compatible", "With --fold-mapped also replace IPv4-compatible ranges (::/96, including :: and ::1) by the IPv4 ranges");
+	opts.optflag("", "lift-mapped", "Replace IPv4 ranges in the input by the IPv4-mapped IPv6 ranges");
+	opts.optflag("", "verify", "Verify generated rules match the input set");
+	opts.optopt("", "max-rules", "Generate at most N rules, including as few additional addresses as possible", "N");
+	opts.optflag("", "optimal", "Generate a guaranteed minimal list (exact instead of heuristic compression)");
+	opts.optflag("", "compare-optimal", "Report (on stderr) if the heuristic compression isn't minimal");
+	opts.optopt("", "include-cost", "Minimise total cost of the rules with cost N for each include rule (default 1)", "N");
+	opts.optopt("", "exclude-cost", "Minimise total cost of the rules with cost N for each exclude rule (default 1)", "N");
+	opts.optmulti("", "length-cost", "Minimise total cost of the rules with additional cost N for each rule with network length LEN (can be repeated; rules are split into longer ones where that is cheaper)", "LEN=N");
+	opts.optopt("", "min-length", "Split rules with a network length shorter than N", "N");
+	opts.optopt("", "max-length", "Fail if the rules would need a network length longer than N", "N");
+	opts.optopt("", "min-length-v4", "--min-length for IPv4 rules (overrides --min-length)", "N");
+	opts.optopt("", "max-length-v4", "--max-length for IPv4 rules (overrides --max-length)", "N");
+	opts.optopt("", "min-length-v6", "--min-length for IPv6 rules (overrides --min-length)", "N");
+	opts.optopt("", "max-length-v6", "--max-length for IPv6 rules (overrides --max-length)", "N");
+	opts.optopt("", "split-limit", "Fail if --min-length would generate more than N rules (default 1048576, 0 for no limit)", "N");
+	opts.optflag("", "round-long", "Round prefixes longer than --max-length outward instead of failing (with a warning)");
+	opts.optopt("", "deaggregate", "Expand the set into all prefixes of network length N instead of generating rules", "N");
+	opts.optflag("", "keep-longer", "Keep prefixes longer than --deaggregate unchanged instead of failing");
+	opts.optopt("", "deaggregate-limit", "Fail if --deaggregate would generate more than N prefixes (default 1048576, 0 for no limit)", "N");
+	opts.optflag("", "stats", "Show statistics of the set and the number of generated rules instead of the rules");
+	opts.optflag("", "diff", "Show aggregated prefixes only in the first (old) or only in the second (new) input file");
+	opts.optopt("", "update", "Show changes (-deleted, +added) to the deployed rules in FILE, keeping as many of them as possible", "FILE");
+	opts.optmulti("", "dont-care", "Addresses from FILE may be included or excluded, whatever needs fewer rules (can be repeated)", "FILE");
+	opts.optflag("", "progress", "Report reading progress and memory usage of the set (on stderr)");
+	opts.optflag("", "per-family", "Read mixed IPv4/IPv6 input and generate rules for each family separately (in sections)");
+	opts.optopt("", "ipv4-output", "Write the IPv4 rules of --per-family to FILE instead of stdout", "FILE");
+	opts.optopt("", "ipv6-output", "Write the IPv6 rules of --per-family to FILE instead of stdout", "FILE");
+	#[cfg(feature = "parallel")]
+	opts.optopt("", "threads", "Compress with up to N threads (default: number of CPUs)", "N");
+	opts.optflag("", "strict", "Abort on invalid input lines (default)");
+	opts.optflag("s", "skip-invalid", "Skip invalid input lines (with a warning)");
 	opts.optflag("h", "help", "print this help menu");
 	let matches = match opts.parse(&args[1..]) {
 		Ok(m) => { m }
@@ -85,7 +277,7 @@ fn get_config() -> Option<Config> {
 			return None;
 		}
 	};
-	if matches.opt_present("h") || !matches.free.is_empty() {
+	if matches.opt_present("h") {
 		print_usage(&program, opts);
 		return None;
 	}
@@ -94,16 +286,309 @@ fn get_config() -> Option<Config> {
 		print_usage(&program, opts);
 		return None;
 	}
+	if matches.opt_present("strict") && matches.opt_present("skip-invalid") {
+		println_stderr!("Error: Need at most one of --strict/--skip-invalid.");
+		print_usage(&program, opts);
+		return None;
+	}
 	if matches.opt_present("ipv4") && matches.opt_present("ipv6") {
 		println_stderr!("Error: Need at most one of --ipv4/--ipv6.");
 		print_usage(&program, opts);
 		return None;
 	}
+	if (matches.opt_present("fold-6to4") || matches.opt_present("fold-nat64") || matches.opt_present("fold-compatible")) && !matches.opt_present("fold-mapped") {
+		println_stderr!("Error: --fold-6to4, --fold-nat64 and --fold-compatible require --fold-mapped");
+		print_usage(&program, opts);
+		return None;
+	}
+	if (matches.opt_present("fold-mapped") && (matches.opt_present("lift-mapped") || matches.opt_present("ipv6")))
+		|| (matches.opt_present("lift-mapped") && matches.opt_present("ipv4"))
+	{
+		println_stderr!("Error: Need at most one of --fold-mapped/--lift-mapped, and not the mode of the other family");
+		print_usage(&program, opts);
+		return None;
+	}
+	if (matches.opt_present("fold-mapped") || matches.opt_present("lift-mapped")) && matches.opt_present("definitions") {
+		println_stderr!("Error: --fold-mapped and --lift-mapped can't be combined with --definitions");
+		print_usage(&program, opts);
+		return None;
+	}
+	let max_rules = match matches.opt_str("max-rules") {
+		None => None,
+		Some(n) => match usize::from_str(&n) {
+			Ok(n) if n > 0 => Some(n),
+			_ => {
+				println_stderr!("Error: Invalid maximum number of rules: {}", n);
+				print_usage(&program, opts);
+				return None;
+			},
+		},
+	};
+	if max_rules.is_some() && (matches.opt_present("complete") || matches.opt_present("aggregate")) {
+		println_stderr!("Error: --max-rules can't be combined with aggregated or complete list");
+		print_usage(&program, opts);
+		return None;
+	}
+	if max_rules.is_some() && matches.opt_present("verify") {
+		println_stderr!("Error: Need at most one of --verify/--max-rules.");
+		print_usage(&program, opts);
+		return None;
+	}
+	let dont_care = matches.opt_strs("dont-care");
+	if !dont_care.is_empty() && (matches.opt_present("complete") || matches.opt_present("aggregate") || max_rules.is_some()) {
+		println_stderr!("Error: --dont-care can't be combined with aggregated or complete list, or --max-rules");
+		print_usage(&program, opts);
+		return None;
+	}
+	if matches.opt_present("optimal") && matches.opt_present("compare-optimal") {
+		println_stderr!("Error: Need at most one of --optimal/--compare-optimal.");
+		print_usage(&program, opts);
+		return None;
+	}
+	if (matches.opt_present("optimal") || matches.opt_present("compare-optimal"))
+		&& (matches.opt_present("complete") || matches.opt_present("aggregate") || max_rules.is_some() || !dont_care.is_empty())
+	{
+		println_stderr!("Error: --optimal/--compare-optimal can't be combined with aggregated or complete list, --max-rules or --dont-care");
+		print_usage(&program, opts);
+		return None;
+	}
+	let mut rule_cost = [None, None];
+	for (ndx, name) in ["include-cost", "exclude-cost"].iter().enumerate() {
+		if let Some(n) = matches.opt_str(name) {
+			match u64::from_str(&n) {
+				Ok(n) => rule_cost[ndx] = Some(n),
+				Err(_) => {
+					println_stderr!("Error: Invalid cost for --{}: {}", name, n);
+					print_usage(&program, opts);
+					return None;
+				},
+			}
+		}
+	}
+	let mut length_costs = Vec::new();
+	for spec in matches.opt_strs("length-cost") {
+		match parse_length_cost(&spec) {
+			Some(c) => length_costs.push(c),
+			None => {
+				println_stderr!("Error: Invalid length cost (expected LEN=COST): {}", spec);
+				print_usage(&program, opts);
+				return None;
+			},
+		}
+	}
+	let costs = rule_costs(rule_cost[0], rule_cost[1], &length_costs);
+	let mut length_limit = [None; 6];
+	for (ndx, name) in LENGTH_OPTIONS.iter().enumerate() {
+		if let Some(n) = matches.opt_str(name) {
+			match usize::from_str(&n) {
+				Ok(n) => length_limit[ndx] = Some(n),
+				Err(_) => {
+					println_stderr!("Error: Invalid length for --{}: {}", name, n);
+					print_usage(&program, opts);
+					return None;
+				},
+			}
+		}
+	}
+	let split_limit = match matches.opt_str("split-limit") {
+		None => None,
+		Some(n) => match u64::from_str(&n) {
+			Ok(n) => Some(output_limit(n)),
+			Err(_) => {
+				println_stderr!("Error: Invalid limit for --split-limit: {}", n);
+				print_usage(&program, opts);
+				return None;
+			},
+		},
+	};
+	let limits = match length_limits(&length_limit, matches.opt_present("round-long"), split_limit, matches.opt_present("ipv4"), matches.opt_present("ipv6")) {
+		Ok(limits) => limits,
+		Err(e) => {
+			println_stderr!("Error: {}", e);
+			print_usage(&program, opts);
+			return None;
+		},
+	};
+	if limits.is_some() && (max_rules.is_some() || !dont_care.is_empty()) {
+		println_stderr!("Error: Length limits can't be combined with --max-rules or --dont-care");
+		print_usage(&program, opts);
+		return None;
+	}
+	if matches.opt_present("round-long") && matches.opt_present("verify") {
+		println_stderr!("Error: Need at most one of --verify/--round-long.");
+		print_usage(&program, opts);
+		return None;
+	}
+	if costs.is_some()
+		&& (matches.opt_present("complete") || matches.opt_present("aggregate") || max_rules.is_some() || !dont_care.is_empty()
+			|| matches.opt_present("optimal") || matches.opt_present("compare-optimal"))
+	{
+		println_stderr!("Error: Rule costs can't be combined with aggregated or complete list, --max-rules, --dont-care, --optimal or --compare-optimal");
+		print_usage(&program, opts);
+		return None;
+	}
+	let deaggregate = match matches.opt_str("deaggregate") {
+		None => None,
+		Some(n) => match usize::from_str(&n) {
+			Ok(n) => Some(n),
+			Err(_) => {
+				println_stderr!("Error: Invalid length for --deaggregate: {}", n);
+				print_usage(&program, opts);
+				return None;
+			},
+		},
+	};
+	let deaggregate_limit = match matches.opt_str("deaggregate-limit") {
+		None => DEFAULT_DEAGGREGATE_LIMIT,
+		Some(n) => match u64::from_str(&n) {
+			Ok(n) => output_limit(n),
+			Err(_) => {
+				println_stderr!("Error: Invalid limit for --deaggregate-limit: {}", n);
+				print_usage(&program, opts);
+				return None;
+			},
+		},
+	};
+	if deaggregate.is_none() && (matches.opt_present("keep-longer") || matches.opt_present("deaggregate-limit")) {
+		println_stderr!("Error: --keep-longer and --deaggregate-limit require --deaggregate");
+		print_usage(&program, opts);
+		return None;
+	}
+	if deaggregate.is_some()
+		&& (matches.opt_present("complete") || matches.opt_present("aggregate") || max_rules.is_some() || !dont_care.is_empty()
+			|| matches.opt_present("optimal") || matches.opt_present("compare-optimal") || costs.is_some() || limits.is_some()
+			|| matches.opt_present("verify") || matches.opt_present("lookup"))
+	{
+		println_stderr!("Error: --deaggregate can't be combined with options generating or checking rules");
+		print_usage(&program, opts);
+		return None;
+	}
+	if matches.opt_present("stats")
+		&& (matches.opt_present("complete") || matches.opt_present("aggregate") || max_rules.is_some() || !dont_care.is_empty()
+			|| matches.opt_present("optimal") || matches.opt_present("compare-optimal") || costs.is_some() || limits.is_some()
+			|| matches.opt_present("verify") || matches.opt_present("lookup") || deaggregate.is_some())
+	{
+		println_stderr!("Error: --stats can't be combined with options generating or checking rules");
+		print_usage(&program, opts);
+		return None;
+	}
+	if matches.opt_present("diff")
+		&& (matches.opt_present("complete") || matches.opt_present("aggregate") || max_rules.is_some() || !dont_care.is_empty()
+			|| matches.opt_present("optimal") || matches.opt_present("compare-optimal") || costs.is_some() || limits.is_some()
+			|| matches.opt_present("verify") || matches.opt_present("lookup") || deaggregate.is_some() || matches.opt_present("stats")
+			|| matches.opt_present("invert") || matches.opt_present("operation"))
+	{
+		println_stderr!("Error: --diff can't be combined with options generating or checking rules, --invert or --operation");
+		print_usage(&program, opts);
+		return None;
+	}
+	if matches.opt_present("diff") && 2 != matches.free.len() {
+		println_stderr!("Error: --diff requires exactly two input files");
+		print_usage(&program, opts);
+		return None;
+	}
+	if matches.opt_present("lookup") && matches.opt_present("definitions")
+		&& (matches.opt_present("complete") || matches.opt_present("aggregate") || max_rules.is_some() || !dont_care.is_empty()
+			|| matches.opt_present("optimal") || matches.opt_present("compare-optimal") || costs.is_some() || limits.is_some()
+			|| matches.opt_present("verify") || matches.opt_present("invert") || matches.opt_present("operation") || matches.free.len() > 1)
+	{
+		println_stderr!("Error: --lookup with --definitions looks up in the definition list as read; it can't be combined with options generating or checking rules, --invert, --operation or more than one input file");
+		print_usage(&program, opts);
+		return None;
+	}
+	if matches.opt_present("update")
+		&& (matches.opt_present("complete") || matches.opt_present("aggregate") || max_rules.is_some() || !dont_care.is_empty()
+			|| matches.opt_present("optimal") || matches.opt_present("compare-optimal") || costs.is_some() || limits.is_some()
+			|| matches.opt_present("lookup") || deaggregate.is_some() || matches.opt_present("stats") || matches.opt_present("diff"))
+	{
+		println_stderr!("Error: --update can't be combined with other options generating rules, --lookup, --stats or --diff");
+		print_usage(&program, opts);
+		return None;
+	}
+	if matches.opt_present("per-family")
+		&& (matches.opt_present("ipv4") || matches.opt_present("ipv6") || matches.opt_present("lookup") || deaggregate.is_some()
+			|| matches.opt_present("stats") || matches.opt_present("diff") || matches.opt_present("update"))
+	{
+		println_stderr!("Error: --per-family can't be combined with --ipv4/--ipv6, --lookup, --deaggregate, --stats, --diff or --update");
+		print_usage(&program, opts);
+		return None;
+	}
+	if !matches.opt_present("per-family") && (matches.opt_present("ipv4-output") || matches.opt_present("ipv6-output")) {
+		println_stderr!("Error: --ipv4-output and --ipv6-output require --per-family");
+		print_usage(&program, opts);
+		return None;
+	}
+	#[cfg(feature = "parallel")]
+	let threads = match matches.opt_str("threads") {
+		None => default_threads(),
+		Some(n) => match usize::from_str(&n) {
+			Ok(n) if n > 0 => n,
+			_ => {
+				println_stderr!("Error: Invalid number of threads: {}", n);
+				print_usage(&program, opts);
+				return None;
+			},
+		},
+	};
+	let host_bits = match matches.opt_str("host-bits") {
+		None => input::HostBitsPolicy::Reject,
+		Some(name) => match parse_host_bits(&name) {
+			Some(p) => p,
+			None => {
+				println_stderr!("Error: Unknown host bits policy: {}", name);
+				print_usage(&program, opts);
+				return None;
+			},
+		},
+	};
+	let operation = match matches.opt_str("operation") {
+		None => set_ops::SetOperation::Union,
+		Some(name) => match parse_operation(&name) {
+			Some(op) => op,
+			None => {
+				println_stderr!("Error: Unknown operation: {}", name);
+				print_usage(&program, opts);
+				return None;
+			},
+		},
+	};
 
 	Option::Some(Config{
 		invert: matches.opt_present("invert"),
 		complete: matches.opt_present("complete"),
 		aggregate: matches.opt_present("aggregate"),
+		skip_invalid: matches.opt_present("skip-invalid"),
+		definitions: matches.opt_present("definitions"),
+		host_bits,
+		embedded_ipv4: embedded_ipv4_policy(
+			matches.opt_present("fold-mapped"),
+			matches.opt_present("fold-6to4"),
+			matches.opt_present("fold-nat64"),
+			matches.opt_present("fold-compatible"),
+			matches.opt_present("lift-mapped"),
+		),
+		verify: matches.opt_present("verify"),
+		max_rules,
+		dont_care,
+		optimal: matches.opt_present("optimal"),
+		compare_optimal: matches.opt_present("compare-optimal"),
+		costs,
+		limits,
+		deaggregate,
+		keep_longer: matches.opt_present("keep-longer"),
+		deaggregate_limit,
+		stats: matches.opt_present("stats"),
+		diff: matches.opt_present("diff"),
+		update: matches.opt_str("update"),
+		progress: matches.opt_present("progress"),
+		per_family: matches.opt_present("per-family"),
+		ipv4_output: matches.opt_str("ipv4-output"),
+		ipv6_output: matches.opt_str("ipv6-output"),
+		#[cfg(feature = "parallel")]
+		threads,
+		operation,
+		lookup: matches.opt_str("lookup"),
+		inputs: matches.free.clone(),
 		protocol: if matches.opt_present("ipv4") {
 			ConfigProtocol::IPv4
 		} else if matches.opt_present("ipv6") {
@@ -118,15 +603,42 @@ fn get_config() -> Option<Config> {
 #[macro_use]
 extern crate clap;
 
+#[cfg(feature = "clap")]
+fn is_max_rules(n: String) -> Result<(), String> {
+	match usize::from_str(&n) {
+		Ok(n) if n > 0 => Ok(()),
+		_ => Err("expected a positive number".to_string()),
+	}
+}
+
+#[cfg(feature = "clap")]
+fn is_length(n: String) -> Result<(), String> {
+	usize::from_str(&n).map(|_| ()).map_err(|_| "expected a number".to_string())
+}
+
+#[cfg(feature = "clap")]
+fn is_cost(n: String) -> Result<(), String> {
+	u64::from_str(&n).map(|_| ()).map_err(|_| "expected a number".to_string())
+}
+
+#[cfg(feature = "clap")]
+fn is_length_cost(spec: String) -> Result<(), String> {
+	parse_length_cost(&spec).map(|_| ()).ok_or_else(|| "expected LEN=COST".to_string())
+}
+
+#[cfg(all(feature = "clap", feature = "parallel"))]
+fn is_threads(n: String) -> Result<(), String> {
+	is_max_rules(n)
+}
+
 #[cfg(feature = "clap")]
 fn get_config() -> Option<Config> {
-	let matches = clap_app!(
+	let app = clap_app!(
 		@app (clap::App::new(NAME))
 		(version: VERSION)
 		(author: AUTHORS)
 		(about: DESC)
 		(@group protocol =>
-			(@attributes +required)
 			(@arg ipv4: short("4") "IPv4 mode")
 			(@arg ipv6: short("6") "IPv6 mode")
 		)
@@ -135,81 +647,739 @@ fn get_config() -> Option<Config> {
 			(@arg aggregate: -a "Aggregate including ranges")
 		)
 		(@arg invert: -i "Invert input list")
-	).get_matches();
+		(@group invalid =>
+			(@arg strict: --strict "Abort on invalid input lines (default)")
+			(@arg skip_invalid: -s --("skip-invalid") "Skip invalid input lines (with a warning)")
+		)
+		(@arg set_operation: -o --operation +takes_value possible_value[union intersection difference xor] "Combine input files with OP (default: union)")
+		(@arg definitions: -d --definitions "Input files contain include/exclude definitions")
+		(@arg host_bits: --("host-bits") +takes_value possible_value[reject truncate host] "Handle CIDR ranges with host bits set (default: reject)")
+		(@arg fold_mapped: --("fold-mapped") conflicts_with[lift_mapped ipv6 definitions] "Replace IPv4-mapped IPv6 ranges (::ffff:0:0/96) in the input by the IPv4 ranges")
+		(@arg fold_6to4: --("fold-6to4") requires[fold_mapped] "With --fold-mapped also replace 6to4 ranges (2002::/16, up to /48) by the IPv4 ranges")
+		(@arg fold_nat64: --("fold-nat64") requires[fold_mapped] "With --fold-mapped also replace NAT64 ranges (64:ff9b::/96) by the IPv4 ranges")
+		(@arg fold_compatible: --("fold-compatible") requires[fold_mapped] "With --fold-mapped also replace IPv4-compatible ranges (::/96, including :: and ::1) by the IPv4 ranges")
+		(@arg lift_mapped: --("lift-mapped") conflicts_with[ipv4 definitions] "Replace IPv4 ranges in the input by the IPv4-mapped IPv6 ranges")
+		(@arg verify: --verify conflicts_with[max_rules] "Verify generated rules match the input set")
+		(@arg max_rules: --("max-rules") +takes_value conflicts_with[complete aggregate] {is_max_rules} "Generate at most N rules, including as few additional addresses as possible")
+		(@arg dont_care: --("dont-care") +takes_value +multiple number_of_values(1) conflicts_with[complete aggregate max_rules] "Addresses from FILE may be included or excluded, whatever needs fewer rules (can be repeated)")
+		(@arg optimal: --optimal conflicts_with[complete aggregate max_rules dont_care compare_optimal] "Generate a guaranteed minimal list (exact instead of heuristic compression)")
+		(@arg compare_optimal: --("compare-optimal") conflicts_with[complete aggregate max_rules dont_care] "Report (on stderr) if the heuristic compression isn't minimal")
+		(@arg include_cost: --("include-cost") +takes_value {is_cost} conflicts_with[complete aggregate max_rules dont_care optimal compare_optimal] "Minimise total cost of the rules with cost N for each include rule (default 1)")
+		(@arg exclude_cost: --("exclude-cost") +takes_value {is_cost} conflicts_with[complete aggregate max_rules dont_care optimal compare_optimal] "Minimise total cost of the rules with cost N for each exclude rule (default 1)")
+		(@arg length_cost: --("length-cost") +takes_value +multiple number_of_values(1) {is_length_cost} conflicts_with[complete aggregate max_rules dont_care optimal compare_optimal] "Minimise total cost of the rules with additional cost N for each rule with network length LEN (LEN=N, can be repeated; rules are split into longer ones where that is cheaper)")
+		(@arg min_length: --("min-length") +takes_value {is_length} conflicts_with[max_rules dont_care] "Split rules with a network length shorter than N")
+		(@arg max_length: --("max-length") +takes_value {is_length} conflicts_with[max_rules dont_care] "Fail if the rules would need a network length longer than N")
+		(@arg min_length_v4: --("min-length-v4") +takes_value {is_length} conflicts_with[max_rules dont_care] "--min-length for IPv4 rules (overrides --min-length)")
+		(@arg max_length_v4: --("max-length-v4") +takes_value {is_length} conflicts_with[max_rules dont_care] "--max-length for IPv4 rules (overrides --max-length)")
+		(@arg min_length_v6: --("min-length-v6") +takes_value {is_length} conflicts_with[max_rules dont_care] "--min-length for IPv6 rules (overrides --min-length)")
+		(@arg max_length_v6: --("max-length-v6") +takes_value {is_length} conflicts_with[max_rules dont_care] "--max-length for IPv6 rules (overrides --max-length)")
+		(@arg split_limit: --("split-limit") +takes_value {is_cost} "Fail if --min-length would generate more than N rules (default 1048576, 0 for no limit)")
+		(@arg round_long: --("round-long") conflicts_with[verify] "Round prefixes longer than --max-length outward instead of failing (with a warning)")
+		(@arg deaggregate: --deaggregate +takes_value {is_length} conflicts_with[complete aggregate max_rules dont_care optimal compare_optimal include_cost exclude_cost length_cost min_length max_length min_length_v4 max_length_v4 min_length_v6 max_length_v6 verify lookup] "Expand the set into all prefixes of network length N instead of generating rules")
+		(@arg keep_longer: --("keep-longer") requires[deaggregate] "Keep prefixes longer than --deaggregate unchanged instead of failing")
+		(@arg deaggregate_limit: --("deaggregate-limit") +takes_value {is_cost} requires[deaggregate] "Fail if --deaggregate would generate more than N prefixes (default 1048576, 0 for no limit)")
+		(@arg stats: --stats conflicts_with[complete aggregate max_rules dont_care optimal compare_optimal include_cost exclude_cost length_cost min_length max_length min_length_v4 max_length_v4 min_length_v6 max_length_v6 verify lookup deaggregate] "Show statistics of the set and the number of generated rules instead of the rules")
+		(@arg diff: --diff requires[INPUT] conflicts_with[complete aggregate max_rules dont_care optimal compare_optimal include_cost exclude_cost length_cost min_length max_length min_length_v4 max_length_v4 min_length_v6 max_length_v6 verify lookup deaggregate stats invert set_operation] "Show aggregated prefixes only in the first (old) or only in the second (new) input file")
+		(@arg update: --update +takes_value conflicts_with[complete aggregate max_rules dont_care optimal compare_optimal include_cost exclude_cost length_cost min_length max_length min_length_v4 max_length_v4 min_length_v6 max_length_v6 lookup deaggregate stats diff] "Show changes (-deleted, +added) to the deployed rules in FILE, keeping as many of them as possible")
+		(@arg progress: --progress "Report reading progress and memory usage of the set (on stderr)")
+		(@arg per_family: --("per-family") conflicts_with[ipv4 ipv6 lookup deaggregate stats diff update] "Read mixed IPv4/IPv6 input and generate rules for each family separately (in sections)")
+		(@arg ipv4_output: --("ipv4-output") +takes_value requires[per_family] "Write the IPv4 rules of --per-family to FILE instead of stdout")
+		(@arg ipv6_output: --("ipv6-output") +takes_value requires[per_family] "Write the IPv6 rules of --per-family to FILE instead of stdout")
+		(@arg lookup: -l --lookup +takes_value "Look up prefixes from FILE ('-' for stdin) in the generated rules (with -d: in the definition list)")
+		(@arg INPUT: ... "Input files ('-' for stdin, default)")
+	);
+	#[cfg(feature = "parallel")]
+	let app = app.arg(clap::Arg::with_name("threads")
+		.long("threads")
+		.takes_value(true)
+		.validator(is_threads)
+		.help("Compress with up to N threads (default: number of CPUs)"));
+	let matches = app.get_matches();
+	if matches.is_present("diff") && 2 != matches.values_of("INPUT").map_or(0, |v| v.count()) {
+		clap::Error::with_description("--diff requires exactly two input files", clap::ErrorKind::WrongNumberOfValues).exit();
+	}
+	if matches.is_present("lookup") && matches.is_present("definitions") {
+		let generating = [
+			"complete", "aggregate", "max_rules", "dont_care", "optimal", "compare_optimal", "include_cost", "exclude_cost", "length_cost",
+			"min_length", "max_length", "min_length_v4", "max_length_v4", "min_length_v6", "max_length_v6", "verify", "invert", "set_operation",
+		];
+		if generating.iter().any(|&name| matches.is_present(name)) || matches.values_of("INPUT").map_or(0, |v| v.count()) > 1 {
+			clap::Error::with_description(
+				"--lookup with --definitions looks up in the definition list as read; it can't be combined with options generating or checking rules, --invert, --operation or more than one input file",
+				clap::ErrorKind::ArgumentConflict,
+			).exit();
+		}
+	}
+	let mut length_limit = [None; 6];
+	for (ndx, name) in LENGTH_OPTIONS.iter().enumerate() {
+		length_limit[ndx] = matches.value_of(name.replace('-', "_")).map(|n| usize::from_str(n).unwrap());
+	}
+	let limits = length_limits(
+		&length_limit,
+		matches.is_present("round_long"),
+		matches.value_of("split_limit").map(|n| output_limit(u64::from_str(n).unwrap())),
+		matches.is_present("ipv4"),
+		matches.is_present("ipv6"),
+	).unwrap_or_else(|e| clap::Error::with_description(&e, clap::ErrorKind::ValueValidation).exit());
 
 	Option::Some(Config{
 		invert: matches.is_present("invert"),
 		complete: matches.is_present("complete"),
 		aggregate: matches.is_present("aggregate"),
-		protocol: if matches.is_present("ipv4") { ConfigProtocol::IPv4 } else { ConfigProtocol::IPv6 },
+		skip_invalid: matches.is_present("skip_invalid"),
+		definitions: matches.is_present("definitions"),
+		host_bits: parse_host_bits(matches.value_of("host_bits").unwrap_or("reject")).unwrap(),
+		embedded_ipv4: embedded_ipv4_policy(
+			matches.is_present("fold_mapped"),
+			matches.is_present("fold_6to4"),
+			matches.is_present("fold_nat64"),
+			matches.is_present("fold_compatible"),
+			matches.is_present("lift_mapped"),
+		),
+		verify: matches.is_present("verify"),
+		max_rules: matches.value_of("max_rules").map(|n| usize::from_str(n).unwrap()),
+		dont_care: matches.values_of("dont_care").map(|v| v.map(String::from).collect()).unwrap_or_default(),
+		optimal: matches.is_present("optimal"),
+		compare_optimal: matches.is_present("compare_optimal"),
+		costs: rule_costs(
+			matches.value_of("include_cost").map(|n| u64::from_str(n).unwrap()),
+			matches.value_of("exclude_cost").map(|n| u64::from_str(n).unwrap()),
+			&matches.values_of("length_cost").map(|v| v.map(|c| parse_length_cost(c).unwrap()).collect::<Vec<_>>()).unwrap_or_default(),
+		),
+		limits,
+		deaggregate: matches.value_of("deaggregate").map(|n| usize::from_str(n).unwrap()),
+		keep_longer: matches.is_present("keep_longer"),
+		deaggregate_limit: matches.value_of("deaggregate_limit").map_or(DEFAULT_DEAGGREGATE_LIMIT, |n| output_limit(u64::from_str(n).unwrap())),
+		stats: matches.is_present("stats"),
+		diff: matches.is_present("diff"),
+		update: matches.value_of("update").map(String::from),
+		progress: matches.is_present("progress"),
+		per_family: matches.is_present("per_family"),
+		ipv4_output: matches.value_of("ipv4_output").map(String::from),
+		ipv6_output: matches.value_of("ipv6_output").map(String::from),
+		#[cfg(feature = "parallel")]
+		threads: matches.value_of("threads").map_or_else(default_threads, |n| usize::from_str(n).unwrap()),
+		operation: parse_operation(matches.value_of("set_operation").unwrap_or("union")).unwrap(),
+		lookup: matches.value_of("lookup").map(String::from),
+		inputs: matches.values_of("INPUT").map(|v| v.map(String::from).collect()).unwrap_or_default(),
+		protocol: if matches.is_present("ipv4") {
+			ConfigProtocol::IPv4
+		} else if matches.is_present("ipv6") {
+			ConfigProtocol::IPv6
+		} else {
+			ConfigProtocol::Any
+		},
 	})
 }
 
-fn read<C>() -> RadixSet<C>
+/// exit code if the input contained invalid lines (`EX_DATAERR`)
+const EXIT_INVALID_INPUT: i32 = 65;
+/// exit code if an input file couldn't be opened (`EX_NOINPUT`)
+const EXIT_NO_INPUT: i32 = 66;
+/// exit code if `--verify` found a difference (`EX_SOFTWARE`)
+const EXIT_VERIFY_FAILED: i32 = 70;
+/// exit code if an output file couldn't be created (`EX_CANTCREAT`)
+const EXIT_CANT_CREATE: i32 = 73;
+/// exit code if reading the input or writing the output failed
+/// (`EX_IOERR`)
+const EXIT_IO_ERROR: i32 = 74;
+
+fn read_input<C>(reader: &mut input::SetReader<C>, name: &str) -> Result<(), i32>
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	use std::io;
+
+	let result = if "-" == name {
+		let stdin = io::stdin();
+		let locked_stdin = stdin.lock();
+		reader.read("<stdin>", locked_stdin)
+	} else {
+		reader.read_file(name)
+	};
+	result.map_err(report_read_error)
+}
+
+// print error, returns exit code
+fn report_read_error(e: input::ReadError) -> i32 {
+	println_stderr!("Error: {}", e);
+	match e {
+		input::ReadError::Open{..} => EXIT_NO_INPUT,
+		input::ReadError::Io{..} => EXIT_IO_ERROR,
+		input::ReadError::Parse(_) => EXIT_INVALID_INPUT,
+	}
+}
+
+// print warnings for skipped lines, returns number of skipped lines
+// and number of adjusted ranges with host bits
+fn report_skipped<C>(reader: &input::SetReader<C>) -> (usize, usize)
 where
-	C: bitstring::BitString+FromStr+fmt::Display+Clone,
+	C: IpPrefix+FromStr+fmt::Display,
 	<C as FromStr>::Err: fmt::Debug,
 {
-	use std::io::{self,BufRead};
+	for e in reader.skipped() {
+		println_stderr!("Warning: skipped {}", e);
+	}
+	(reader.skipped().len(), reader.host_bits_adjusted())
+}
 
-	let stdin = io::stdin();
-	let locked_stdin = stdin.lock();
+fn add_counts(a: (usize, usize), b: (usize, usize)) -> (usize, usize) {
+	(a.0 + b.0, a.1 + b.1)
+}
 
-	let mut s = RadixSet::<C>::default();
-	for line in locked_stdin.lines() {
-		let l = line.unwrap();
-		if !l.is_empty() && l.as_bytes()[0] != ('#' as u8) {
-			s.insert(C::from_str(&l).unwrap());
+fn read_options(config: &Config) -> input::ReadOptions {
+	input::ReadOptions{
+		invalid_lines: if config.skip_invalid {
+			input::InvalidLinePolicy::Skip
+		} else {
+			input::InvalidLinePolicy::Strict
+		},
+		format: if config.definitions {
+			input::InputFormat::Definitions
+		} else {
+			input::InputFormat::Prefixes
+		},
+		host_bits: config.host_bits,
+		embedded_ipv4: config.embedded_ipv4,
+	}
+}
+
+/// number of lines between progress reports (`--progress`)
+const PROGRESS_LINES: u64 = 1_000_000;
+
+const MIB: f64 = 1048576.0;
+
+fn new_reader<C>(config: &Config) -> input::SetReader<C>
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	let mut reader = input::SetReader::with_options(read_options(config));
+	if config.progress {
+		reader.set_progress(PROGRESS_LINES, |progress| {
+			println_stderr!("Progress: read {} lines ({:.1} MiB)", progress.lines, progress.bytes as f64 / MIB);
+		});
+	}
+	reader
+}
+
+// print summary of `--progress`
+fn report_progress<C>(progress: input::ReadProgress, start: Instant, set: &RadixSet<C>, config: &Config)
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	if config.progress {
+		println_stderr!(
+			"Progress: read {} lines ({:.1} MiB) in {:.1}s; the set has {} prefixes using about {:.1} MiB",
+			progress.lines, progress.bytes as f64 / MIB, start.elapsed().as_secs_f64(),
+			set.iter().count(), stats::estimated_memory(set) as f64 / MIB,
+		);
+	}
+}
+
+// print summary warnings for skipped lines and adjusted ranges
+fn report_counts(counts: (usize, usize), config: &Config) {
+	if 0 != counts.0 {
+		println_stderr!("Warning: skipped {} invalid line(s)", counts.0);
+	}
+	if 0 != counts.1 {
+		let action = match config.host_bits {
+			input::HostBitsPolicy::Truncate => "truncated to the network",
+			_ => "used as single address",
+		};
+		println_stderr!("Warning: {} CIDR range(s) with host bits set {}", counts.1, action);
+	}
+}
+
+// returns the set, the number of entries read and the number of input
+// lines (including comments, empty and skipped lines)
+fn read<C>(config: &Config) -> Result<(RadixSet<C>, usize, u64), i32>
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	let start = Instant::now();
+	let stdin_input = vec!["-".to_string()];
+	let inputs = if config.inputs.is_empty() { &stdin_input } else { &config.inputs };
+	let mut skipped = (0, 0);
+	let mut entries = 0;
+	let mut progress = input::ReadProgress::default();
+
+	let set = if set_ops::SetOperation::Union == config.operation {
+		// simply merge all inputs into one set
+		let mut reader = new_reader(config);
+		for name in inputs {
+			read_input(&mut reader, name)?;
 		}
+		skipped = add_counts(skipped, report_skipped(&reader));
+		entries = reader.entries();
+		progress = reader.progress();
+		reader.into_set()
+	} else {
+		let mut sets = Vec::new();
+		for name in inputs {
+			let mut reader = new_reader(config);
+			read_input(&mut reader, name)?;
+			skipped = add_counts(skipped, report_skipped(&reader));
+			entries += reader.entries();
+			progress.lines += reader.progress().lines;
+			progress.bytes += reader.progress().bytes;
+			sets.push(reader.into_set());
+		}
+		config.operation.fold(sets)
+	};
+
+	report_counts(skipped, config);
+	report_progress(progress, start, &set, config);
+
+	Ok((set, entries, progress.lines))
+}
+
+// read the union of all `--dont-care` files (in the same format as
+// the inputs)
+fn read_dont_care<C>(config: &Config) -> Result<RadixSet<C>, i32>
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	let mut reader = new_reader(config);
+	for name in &config.dont_care {
+		read_input(&mut reader, name)?;
 	}
+	report_counts(report_skipped(&reader), config);
+	Ok(reader.into_set())
+}
 
-	s
+#[cfg(feature = "parallel")]
+fn compress<C: IpPrefix+Send+Sync>(set: &RadixSet<C>, invert: bool, config: &Config) -> Vec<set_def::Definition<C>> {
+	set_def::Definition::compress_parallel(set, invert, config.threads)
 }
 
-fn show_compress<C>(set: &RadixSet<C>, invert: bool)
+#[cfg(not(feature = "parallel"))]
+fn compress<C: IpPrefix>(set: &RadixSet<C>, invert: bool, _config: &Config) -> Vec<set_def::Definition<C>> {
+	set_def::Definition::compress(set, invert)
+}
+
+fn generate<C>(set: &RadixSet<C>, dont_care: &RadixSet<C>, config: &Config) -> Result<Vec<set_def::Definition<C>>, i32>
 where
-	C: bitstring::BitString+FromStr+fmt::Display+Clone,
+	C: IpPrefix+FromStr+fmt::Display+Send+Sync,
 	<C as FromStr>::Err: fmt::Debug,
 {
-	print!("{}", WriteLinesIter::from(
-		set_def::Definition::compress(set, invert)
-	));
+	if let Some(max_rules) = config.max_rules {
+		let lossy = set_def::Definition::compress_lossy(set, config.invert, max_rules)
+			.expect("max_rules must be positive");
+		if 0 != lossy.over_covered {
+			println_stderr!("Warning: rules include {} address(es) not in the input set", lossy.over_covered);
+		}
+		return Ok(lossy.definitions);
+	}
+	if dont_care.root().is_some() {
+		return Ok(set_def::Definition::compress_dont_care(set, dont_care, config.invert));
+	}
+
+	let complete = config.complete || config.aggregate;
+	let base = |set: &RadixSet<C>, invert: bool| {
+		if complete {
+			set_def::Definition::complete(set, invert)
+		} else if let Some(ref costs) = config.costs {
+			set_def::Definition::compress_costs(set, invert, costs)
+		} else if config.optimal {
+			set_def::Definition::compress_optimal(set, invert)
+		} else {
+			let defs = compress(set, invert, config);
+			if config.compare_optimal {
+				let optimal = set_def::Definition::optimal_len(set, invert);
+				if defs.len() > optimal {
+					println_stderr!("Warning: heuristic compression generated {} rules, but {} are enough", defs.len(), optimal);
+				}
+			}
+			defs
+		}
+	};
+	let limits = match config.limits {
+		None => return Ok(base(set, config.invert)),
+		Some(ref limits) => limits,
+	};
+	// excluding parts of split rules are only needed for the complete
+	// list; `-a` only shows the including rules
+	match set_def::Definition::limit_lengths(set, config.invert, limits, config.complete, base) {
+		Ok(limited) => {
+			for prefix in &limited.rounded {
+				let max = prefix.prefix_address_length().and_then(|a| limits.bounds(a).max).unwrap_or(0);
+				println_stderr!("Warning: rounded {} outward to /{}", prefix, max);
+			}
+			if 0 != limited.over_covered {
+				println_stderr!("Warning: rules include {} address(es) not in the input set", limited.over_covered);
+			}
+			Ok(limited.definitions)
+		},
+		Err(limits::LimitError::TooManyRules(e)) => {
+			println_stderr!("Error: splitting short rules would generate {} rules (limit {}, see --split-limit)", e.count, e.limit);
+			Err(EXIT_INVALID_INPUT)
+		},
+		Err(e) => {
+			println_stderr!("Error: {}", e);
+			Err(EXIT_INVALID_INPUT)
+		},
+	}
 }
 
-fn show_complete<C: bitstring::BitString>(set: &RadixSet<C>, invert: bool)
+fn verify<C>(set: &RadixSet<C>, dont_care: &RadixSet<C>, defs: &[set_def::Definition<C>], config: &Config) -> Result<(), i32>
 where
-	C: bitstring::BitString+FromStr+fmt::Display+Clone,
+	C: IpPrefix+FromStr+fmt::Display,
 	<C as FromStr>::Err: fmt::Debug,
 {
-	print!("{}", WriteLinesIter::from(
-		set_def::Definition::complete(set, invert)
-	))
+	match set_def::Definition::verify_dont_care(defs, set, dont_care, config.invert) {
+		Ok(()) => Ok(()),
+		Err(mismatch) => {
+			println_stderr!("Error: verification failed: {}", mismatch);
+			Err(EXIT_VERIFY_FAILED)
+		},
+	}
 }
 
-fn show_aggregate<C: bitstring::BitString>(set: &RadixSet<C>, invert: bool)
+fn write_rules<C, W>(out: &mut W, defs: &[set_def::Definition<C>], config: &Config) -> std::io::Result<()>
 where
-	C: bitstring::BitString+FromStr+fmt::Display+Clone,
+	C: IpPrefix+FromStr+fmt::Display,
 	<C as FromStr>::Err: fmt::Debug,
+	W: std::io::Write,
 {
-	for def in set_def::Definition::complete(set, invert).into_iter() {
-		if def.include {
-			println!("{}", def.prefix);
+	if config.aggregate {
+		for def in defs {
+			if def.label {
+				writeln!(out, "{}", def.prefix)?;
+			}
 		}
+		Ok(())
+	} else {
+		write!(out, "{}", WriteLinesIter::from(defs))
 	}
 }
 
-fn show<C: bitstring::BitString>(set: &RadixSet<C>, config: &Config)
+fn show<C>(defs: &[set_def::Definition<C>], config: &Config)
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	let stdout = std::io::stdout();
+	write_rules(&mut stdout.lock(), defs, config).expect("failed printing to stdout");
+}
+
+fn deaggregate<C>(set: &RadixSet<C>, len: usize, config: &Config) -> Result<(), i32>
 where
-	C: bitstring::BitString+FromStr+fmt::Display+Clone,
+	C: IpPrefix+FromStr+fmt::Display,
 	<C as FromStr>::Err: fmt::Debug,
 {
-	if config.complete {
-		show_complete(set, config.invert);
-	} else if config.aggregate {
-		show_aggregate(set, config.invert);
+	use std::io::{self,BufWriter,Write};
+
+	let inverted;
+	let set = if config.invert {
+		inverted = set_ops::combine(set, set, |a, _| !a);
+		&inverted
+	} else {
+		set
+	};
+	let count = deaggregate::deaggregate_count(set, len);
+	if count > config.deaggregate_limit {
+		println_stderr!("Error: deaggregation would generate {} prefixes (limit {}, see --deaggregate-limit)", count, config.deaggregate_limit);
+		return Err(EXIT_INVALID_INPUT);
+	}
+	let prefixes = if config.keep_longer {
+		deaggregate::deaggregate(set, len)
 	} else {
-		show_compress(set, config.invert);
+		match deaggregate::deaggregate_exact(set, len) {
+			Ok(prefixes) => prefixes,
+			Err(e) => {
+				println_stderr!("Error: {}", e);
+				return Err(EXIT_INVALID_INPUT);
+			},
+		}
+	};
+
+	let stdout = io::stdout();
+	let mut out = BufWriter::new(stdout.lock());
+	for prefix in prefixes {
+		if writeln!(out, "{}", prefix).is_err() {
+			return Err(EXIT_IO_ERROR);
+		}
 	}
+	out.flush().map_err(|_| EXIT_IO_ERROR)
+}
+
+fn show_family(name: &str, family: &stats::FamilyStats) {
+	println!("{} prefixes: {}", name, family.prefixes);
+	println!("{} addresses: {}", name, family.addresses);
+	for (len, &count) in family.lengths.iter().enumerate() {
+		if 0 != count {
+			println!("{} /{}: {}", name, len, count);
+		}
+	}
+}
+
+fn stats<C>(set: &RadixSet<C>, entries: usize, lines: u64, config: &Config)
+where
+	C: IpPrefix+FromStr+fmt::Display+Send+Sync,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	let inverted;
+	let set = if config.invert {
+		inverted = set_ops::combine(set, set, |a, _| !a);
+		&inverted
+	} else {
+		set
+	};
+	let set_stats = stats::SetStats::new(set);
+	println!("input lines: {}", lines);
+	println!("input entries: {}", entries);
+	if !matches!(config.protocol, ConfigProtocol::IPv6) {
+		show_family("IPv4", &set_stats.ipv4);
+	}
+	if !matches!(config.protocol, ConfigProtocol::IPv4) {
+		show_family("IPv6", &set_stats.ipv6);
+		println!("IPv6 /64 networks: {}", set_stats.ipv6_networks);
+	}
+	let complete = set_def::Definition::complete(set, false);
+	println!("rules (compress): {}", compress(set, false, config).len());
+	println!("rules (complete): {}", complete.len());
+	println!("rules (aggregate): {}", complete.iter().filter(|def| def.label).count());
+}
+
+fn diff<C>(config: &Config) -> Result<(), i32>
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	let mut sets = Vec::new();
+	for name in &config.inputs {
+		let start = Instant::now();
+		let mut reader = new_reader::<C>(config);
+		read_input(&mut reader, name)?;
+		report_counts(report_skipped(&reader), config);
+		let progress = reader.progress();
+		let set = reader.into_set();
+		report_progress(progress, start, &set, config);
+		sets.push(set);
+	}
+	let diff = diff::diff(&sets[0], &sets[1]);
+	for prefix in &diff.removed {
+		println!("-{}", prefix);
+	}
+	for prefix in &diff.added {
+		println!("+{}", prefix);
+	}
+	println!("# removed {} address(es) in {} prefix(es)", diff.removed_addresses, diff.removed.len());
+	println!("# added {} address(es) in {} prefix(es)", diff.added_addresses, diff.added.len());
+	Ok(())
+}
+
+// read a definition list as is ('-' for stdin)
+fn read_definition_list<C>(name: &str, policy: input::InvalidLinePolicy) -> Result<Vec<set_def::Definition<C>>, i32>
+where
+	C: IpPrefix+FromStr,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	use std::fs::File;
+	use std::io::{self,BufReader};
+
+	let mut skipped = Vec::new();
+	let defs = if "-" == name {
+		let stdin = io::stdin();
+		let locked_stdin = stdin.lock();
+		input::read_definitions_with("<stdin>", locked_stdin, policy, &mut skipped)
+	} else {
+		match File::open(name) {
+			Ok(f) => input::read_definitions_with(name, BufReader::new(f), policy, &mut skipped),
+			Err(e) => Err(input::ReadError::Open{
+				source: name.to_string(),
+				error: e,
+			}),
+		}
+	}.map_err(report_read_error)?;
+	for e in &skipped {
+		println_stderr!("Warning: skipped {}", e);
+	}
+	if !skipped.is_empty() {
+		println_stderr!("Warning: skipped {} invalid line(s)", skipped.len());
+	}
+	Ok(defs)
+}
+
+fn update<C>(set: &RadixSet<C>, config: &Config, deployed: &str) -> Result<(), i32>
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	// the deployed rules must be complete; never skip any of them
+	let old = read_definition_list(deployed, input::InvalidLinePolicy::Strict)?;
+
+	let update = set_def::Definition::compress_update(set, config.invert, &old);
+	if config.verify {
+		verify(set, &RadixSet::default(), &update.definitions, config)?;
+	}
+	for def in &update.deleted {
+		println!("-{}", def);
+	}
+	for def in &update.added {
+		println!("+{}", def);
+	}
+	println!("# deleted {} rule(s), added {} rule(s), {} rule(s) in total", update.deleted.len(), update.added.len(), update.definitions.len());
+	Ok(())
+}
+
+// look up the prefixes from `queries` in `defs`
+fn lookup<C>(defs: &[set_def::Definition<C>], config: &Config, queries: &str) -> Result<(), i32>
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	use std::fs::File;
+	use std::io::{self,BufRead,BufReader};
+	use set_def::{Definition,Lookup};
+
+	let (source, reader) : (String, Box<dyn BufRead>) = if "-" == queries {
+		("<stdin>".to_string(), Box::new(BufReader::new(io::stdin())))
+	} else {
+		match File::open(queries) {
+			Ok(f) => (queries.to_string(), Box::new(BufReader::new(f))),
+			Err(e) => return Err(report_read_error(input::ReadError::Open{
+				source: queries.to_string(),
+				error: e,
+			})),
+		}
+	};
+
+	let mut skipped = 0;
+	for (ndx, line) in reader.lines().enumerate() {
+		let l = match line {
+			Ok(l) => l,
+			Err(e) => return Err(report_read_error(input::ReadError::Io{
+				source,
+				line: ndx + 1,
+				error: e,
+			})),
+		};
+		let query : C = match input::parse_line(&source, ndx + 1, &l) {
+			None => continue,
+			Some(Ok(q)) => q,
+			Some(Err(e)) => {
+				if !config.skip_invalid {
+					return Err(report_read_error(e.into()));
+				}
+				println_stderr!("Warning: skipped {}", e);
+				skipped += 1;
+				continue;
+			},
+		};
+		match Definition::lookup(defs, &query) {
+			Lookup::Default => println!("{} exclude (default)", query),
+			Lookup::Match(def) => println!("{} {}", query, def),
+			Lookup::Covered(true) => println!("{} include (more specific rules)", query),
+			Lookup::Covered(false) => println!("{} exclude (more specific rules)", query),
+			Lookup::Partial(_) => println!("{} partial", query),
+		}
+	}
+
+	if 0 != skipped {
+		println_stderr!("Warning: skipped {} invalid query line(s)", skipped);
+	}
+
+	Ok(())
+}
+
+fn run<C>(config: &Config) -> Result<(), i32>
+where
+	C: IpPrefix+FromStr+fmt::Display+Send+Sync,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	if config.diff {
+		return diff::<C>(config);
+	}
+	if let (true, Some(queries)) = (config.definitions, config.lookup.as_ref()) {
+		// look up in the definition list as written, not in rules
+		// generated from it
+		let defs = read_definition_list::<C>(config.inputs.first().map_or("-", String::as_str), read_options(config).invalid_lines)?;
+		return lookup(&defs, config, queries);
+	}
+	let (set, entries, lines) = read::<C>(config)?;
+	let dont_care = read_dont_care::<C>(config)?;
+	if config.stats {
+		stats(&set, entries, lines, config);
+		return Ok(());
+	}
+	if let Some(len) = config.deaggregate {
+		return deaggregate(&set, len, config);
+	}
+	if let Some(ref deployed) = config.update {
+		return update(&set, config, deployed);
+	}
+	match config.lookup {
+		Some(ref queries) => {
+			let defs = generate(&set, &dont_care, config)?;
+			if config.verify {
+				verify(&set, &dont_care, &defs, config)?;
+			}
+			lookup(&defs, config, queries)
+		},
+		None => {
+			let defs = generate(&set, &dont_care, config)?;
+			if config.verify {
+				verify(&set, &dont_care, &defs, config)?;
+			}
+			show(&defs, config);
+			Ok(())
+		},
+	}
+}
+
+// show the rules of one family of `--per-family` in a section, or
+// write them to `output`
+fn show_family_rules<C>(name: &str, defs: &[set_def::Definition<C>], config: &Config, output: Option<&String>) -> Result<(), i32>
+where
+	C: IpPrefix+FromStr+fmt::Display,
+	<C as FromStr>::Err: fmt::Debug,
+{
+	use std::fs::File;
+	use std::io::{BufWriter,Write};
+
+	let path = match output {
+		None => {
+			println!("# {}", name);
+			show(defs, config);
+			return Ok(());
+		},
+		Some(path) => path,
+	};
+	let mut out = match File::create(path) {
+		Ok(f) => BufWriter::new(f),
+		Err(e) => {
+			println_stderr!("Error: couldn't create {}: {}", path, e);
+			return Err(EXIT_CANT_CREATE);
+		},
+	};
+	write_rules(&mut out, defs, config).and_then(|_| out.flush()).map_err(|e| {
+		println_stderr!("Error: failed writing {}: {}", path, e);
+		EXIT_IO_ERROR
+	})
+}
+
+// read mixed input, and generate rules for each family separately
+fn run_per_family(config: &Config) -> Result<(), i32> {
+	let (set, _, _) = read::<cidr::AnyIpCidr>(config)?;
+	let dont_care = read_dont_care::<cidr::AnyIpCidr>(config)?;
+	let (ipv4, ipv6) = family::split(&set);
+	let (dont_care_ipv4, dont_care_ipv6) = family::split(&dont_care);
+
+	// generate both lists before writing anything
+	let ipv4_defs = generate(&ipv4, &dont_care_ipv4, config)?;
+	let ipv6_defs = generate(&ipv6, &dont_care_ipv6, config)?;
+	if config.verify {
+		verify(&ipv4, &dont_care_ipv4, &ipv4_defs, config)?;
+		verify(&ipv6, &dont_care_ipv6, &ipv6_defs, config)?;
+	}
+	show_family_rules("IPv4", &ipv4_defs, config, config.ipv4_output.as_ref())?;
+	show_family_rules("IPv6", &ipv6_defs, config, config.ipv6_output.as_ref())
 }
 
 fn main() {
@@ -218,9 +1388,16 @@ fn main() {
 		None => return,
 	};
 
-	match config.protocol {
-		ConfigProtocol::IPv4 => show(&read::<cidr::Ipv4Cidr>(), &config),
-		ConfigProtocol::IPv6 => show(&read::<cidr::Ipv6Cidr>(), &config),
-		ConfigProtocol::Any => show(&read::<cidr::AnyIpCidr>(), &config),
+	let result = if config.per_family {
+		run_per_family(&config)
+	} else {
+		match config.protocol {
+			ConfigProtocol::IPv4 => run::<cidr::Ipv4Cidr>(&config),
+			ConfigProtocol::IPv6 => run::<cidr::Ipv6Cidr>(&config),
+			ConfigProtocol::Any => run::<cidr::AnyIpCidr>(&config),
+		}
+	};
+	if let Err(code) = result {
+		std::process::exit(code);
 	}
 }
@@ -0,0 +1,100 @@
+//! common interface of the supported CIDR types
+use bitstring::BitString;
+use cidr::{AnyIpCidr,Cidr,Ipv4Cidr,Ipv6Cidr};
+
+/// Bit string representation of IP networks
+pub trait IpPrefix: BitString+Clone {
+	/// Number of bits at the start of the bit string representation
+	/// selecting the address family (1 for `AnyIpCidr`, 0 otherwise)
+	const FAMILY_BITS: usize;
+
+	/// Number of bits in an address of the family of the prefix (32
+	/// for IPv4, 128 for IPv6); `None` if the prefix doesn't select a
+	/// family (`AnyIpCidr::Any`).
+	fn prefix_address_length(&self) -> Option<usize>;
+
+	/// Network length of the prefix; `None` if the prefix doesn't
+	/// select a family (`AnyIpCidr::Any`).
+	fn prefix_network_length(&self) -> Option<usize> {
+		self.prefix_address_length().map(|_| self.len() - Self::FAMILY_BITS)
+	}
+
+	/// Number of addresses in the prefix; saturates at `u128::MAX`
+	/// (i.e. `::/0` and `any` are counted as `2^128 - 1` addresses).
+	fn address_count(&self) -> u128 {
+		match (self.prefix_address_length(), self.prefix_network_length()) {
+			(Some(a), Some(n)) if a - n < 128 => 1u128 << (a - n),
+			_ => u128::MAX,
+		}
+	}
+
+	/// Converts from the representation of mixed families; `None` if
+	/// the prefix isn't of the family of `Self`.
+	fn from_any(prefix: AnyIpCidr) -> Option<Self>;
+
+	/// Whether the prefix represents a single address
+	fn is_host(&self) -> bool {
+		match self.prefix_address_length() {
+			Some(a) => self.len() == Self::FAMILY_BITS + a,
+			None => false,
+		}
+	}
+}
+
+impl IpPrefix for Ipv4Cidr {
+	const FAMILY_BITS: usize = 0;
+
+	fn prefix_address_length(&self) -> Option<usize> {
+		Some(32)
+	}
+
+	fn from_any(prefix: AnyIpCidr) -> Option<Self> {
+		match prefix {
+			AnyIpCidr::V4(p) => Some(p),
+			_ => None,
+		}
+	}
+
+	fn prefix_network_length(&self) -> Option<usize> {
+		Some(Cidr::network_length(self) as usize)
+	}
+}
+
+impl IpPrefix for Ipv6Cidr {
+	const FAMILY_BITS: usize = 0;
+
+	fn prefix_address_length(&self) -> Option<usize> {
+		Some(128)
+	}
+
+	fn from_any(prefix: AnyIpCidr) -> Option<Self> {
+		match prefix {
+			AnyIpCidr::V6(p) => Some(p),
+			_ => None,
+		}
+	}
+
+	fn prefix_network_length(&self) -> Option<usize> {
+		Some(Cidr::network_length(self) as usize)
+	}
+}
+
+impl IpPrefix for AnyIpCidr {
+	const FAMILY_BITS: usize = 1;
+
+	fn prefix_address_length(&self) -> Option<usize> {
+		match *self {
+			AnyIpCidr::Any => None,
+			AnyIpCidr::V4(_) => Some(32),
+			AnyIpCidr::V6(_) => Some(128),
+		}
+	}
+
+	fn from_any(prefix: AnyIpCidr) -> Option<Self> {
+		Some(prefix)
+	}
+
+	fn prefix_network_length(&self) -> Option<usize> {
+		AnyIpCidr::network_length(self).map(|l| l as usize)
+	}
+}
@@ -0,0 +1,142 @@
+//! inclusive address ranges (`FIRST-LAST`)
+use bitstring::BitString;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// whether all bits in `key[from..]` are `bit`
+fn all_bits_from<S: BitString>(key: &S, from: usize, bit: bool) -> bool {
+	(from..key.len()).all(|i| bit == key.get(i))
+}
+
+// prefix `key[0..len]` with the bit at `len` replaced by `bit`
+fn sibling_half<S: BitString+Clone>(key: &S, len: usize, bit: bool) -> S {
+	let mut half = key.clone();
+	half.clip(len + 1);
+	if bit != half.get(len) {
+		half.flip(len);
+	}
+	half
+}
+
+// cover `first` up to the end of the prefix `first[0..from]`
+fn cover_from<S: BitString+Clone>(first: &S, from: usize, list: &mut Vec<S>) {
+	if all_bits_from(first, from, false) {
+		let mut prefix = first.clone();
+		prefix.clip(from);
+		list.push(prefix);
+	} else if first.get(from) {
+		cover_from(first, from + 1, list);
+	} else {
+		cover_from(first, from + 1, list);
+		list.push(sibling_half(first, from, true));
+	}
+}
+
+// cover the start of the prefix `last[0..from]` up to `last`
+fn cover_to<S: BitString+Clone>(last: &S, from: usize, list: &mut Vec<S>) {
+	if all_bits_from(last, from, true) {
+		let mut prefix = last.clone();
+		prefix.clip(from);
+		list.push(prefix);
+	} else if last.get(from) {
+		list.push(sibling_half(last, from, false));
+		cover_to(last, from + 1, list);
+	} else {
+		cover_to(last, from + 1, list);
+	}
+}
+
+/// Returns the minimal (sorted) list of prefixes covering exactly all
+/// addresses from `first` to `last` (inclusive).
+///
+/// `first` and `last` need to be single addresses of the same family
+/// (i.e. bit strings of the same length) with `first <= last`.
+///
+/// # Panics
+///
+/// Panics if the bit strings have different lengths or `first > last`.
+pub fn cover<S: BitString+Clone>(first: &S, last: &S) -> Vec<S> {
+	assert_eq!(first.len(), last.len());
+	assert!(Ordering::Greater != first.lexicographic_cmp(last));
+
+	let mut list = Vec::new();
+	let shared = first.shared_prefix_len(last);
+	if shared == first.len() {
+		list.push(first.clone());
+	} else if all_bits_from(first, shared, false) && all_bits_from(last, shared, true) {
+		let mut prefix = first.clone();
+		prefix.clip(shared);
+		list.push(prefix);
+	} else {
+		// first[shared] = 0, last[shared] = 1
+		cover_from(first, shared + 1, &mut list);
+		cover_to(last, shared + 1, &mut list);
+	}
+	list
+}
+
+/// Error returned when parsing a range
+#[derive(Clone,PartialEq,Eq)]
+pub enum ParseRangeError<E> {
+	/// range didn't contain exactly one `-`
+	InvalidSyntax,
+	/// failed to parse the first address
+	First(E),
+	/// failed to parse the last address
+	Last(E),
+	/// first or last address was a network instead of an address
+	NotAnAddress,
+	/// first and last address are from different families
+	FamilyMismatch,
+	/// first address was after the last address
+	Reversed,
+}
+
+impl<E: fmt::Debug> fmt::Debug for ParseRangeError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ParseRangeError::InvalidSyntax => write!(f, "expected range \"FIRST-LAST\""),
+			ParseRangeError::First(ref e) => write!(f, "couldn't parse first address of range: {:?}", e),
+			ParseRangeError::Last(ref e) => write!(f, "couldn't parse last address of range: {:?}", e),
+			ParseRangeError::NotAnAddress => write!(f, "range boundaries must be addresses, not networks"),
+			ParseRangeError::FamilyMismatch => write!(f, "range boundaries must be of the same address family"),
+			ParseRangeError::Reversed => write!(f, "first address of range is after last address"),
+		}
+	}
+}
+
+impl<E: fmt::Debug> fmt::Display for ParseRangeError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(self, f)
+	}
+}
+
+impl<E: fmt::Debug> Error for ParseRangeError<E> {
+}
+
+/// Parses an inclusive range `FIRST-LAST` (whitespace around the `-`
+/// is allowed) and returns the minimal list of prefixes covering it.
+pub fn parse_range<C>(s: &str) -> Result<Vec<C>, ParseRangeError<C::Err>>
+where
+	C: BitString+FromStr+Clone,
+{
+	let mut parts = s.split('-');
+	let (first, last) = match (parts.next(), parts.next(), parts.next()) {
+		(Some(f), Some(l), None) => (f.trim(), l.trim()),
+		_ => return Err(ParseRangeError::InvalidSyntax),
+	};
+	if first.contains('/') || last.contains('/') {
+		return Err(ParseRangeError::NotAnAddress);
+	}
+	let first = C::from_str(first).map_err(ParseRangeError::First)?;
+	let last = C::from_str(last).map_err(ParseRangeError::Last)?;
+	if first.len() != last.len() {
+		return Err(ParseRangeError::FamilyMismatch);
+	}
+	if Ordering::Greater == first.lexicographic_cmp(&last) {
+		return Err(ParseRangeError::Reversed);
+	}
+	Ok(cover(&first, &last))
+}
@@ -1,139 +1,357 @@
 /* create "minimal" definition with positive+negative prefixes */
 use bitstring::BitString;
-use bitstring_trees::set::{RadixSet,Node};
+use bitstring_trees::map::RadixMap;
+use bitstring_trees::set::{InnerNode,RadixSet,Node};
+use prefix::IpPrefix;
+use set_ops;
+use std::cmp::min;
+use std::collections::BTreeSet;
+use std::error::Error;
 use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "parallel")]
+use std::thread;
 
+/// A rule in a longest-prefix-match table: all addresses in `prefix`
+/// (unless matched by a longer prefix) get `label`.
+///
+/// With the default `bool` label `true` means "include" and `false`
+/// means "exclude".
 #[derive(Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
-pub struct Definition<S: BitString> {
+pub struct Definition<S: BitString, L = bool> {
 	pub prefix: S,
-	pub include: bool,
+	pub label: L,
+}
+
+/// How the positive and negative definition lists of the children of
+/// an inner node are combined (see `Definition::compress`)
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub(crate) enum Combine {
+	/// append the lists of the right child to the lists of the left
+	/// child
+	Append,
+	/// the negative list is too long: exclude the node, then the
+	/// positive list
+	ExcludeNode,
+	/// the positive list is too long: include the node, then the
+	/// negative list
+	IncludeNode,
+}
+
+/// Combines the summed up lengths of the positive and negative lists of
+/// the children of an inner node; returns the lengths of the lists of
+/// the node.
+pub(crate) fn combine_counts(pos: usize, neg: usize) -> (Combine, (usize, usize)) {
+	match pos as isize - neg as isize {
+		-1..=1 => (Combine::Append, (pos, neg)),
+		n if n < 0 => (Combine::ExcludeNode, (pos, pos + 1)),
+		_ => (Combine::IncludeNode, (neg + 1, neg)),
+	}
+}
+
+/// Lengths of the positive and negative lists of a node if the
+/// parent context starts `pathlen` bits above the node key.
+///
+/// The positive list assumes the context is excluded by the parent,
+/// and will therefore start with an including definition.  The
+/// negative list assumes the context is already included by the
+/// parent, and will start with an excluding definition (or be empty,
+/// iff the node is a leaf and `pathlen == 0`).
+pub(crate) fn branch_counts(pathlen: usize, pos: usize, neg: usize) -> (usize, usize) {
+	match pathlen {
+		0 => (pos, neg),
+		// the next branch would have the same length, but would
+		// exclude a higher level than necessary - keep excludes longer
+		// if possible
+		1 if pos >= neg => (pos, neg + 1),
+		_ if pos < neg + 2 => (pos, pos + 1),
+		_ => (pos, neg + 2),
+	}
+}
+
+/// Appends the definition for an inner node combined according to
+/// `combine` (if the positive or negative list needs one); returns
+/// which list of the children to append next (`true` for positive).
+pub(crate) fn combine_emit<S: BitString+Clone>(key: &S, combine: Combine, positive: bool, list: &mut Vec<Definition<S>>) -> bool {
+	match combine {
+		Combine::Append => positive,
+		Combine::ExcludeNode => {
+			if !positive {
+				list.push(Definition{
+					prefix: key.clone(),
+					label: false,
+				});
+			}
+			true
+		},
+		Combine::IncludeNode => {
+			if positive {
+				list.push(Definition{
+					prefix: key.clone(),
+					label: true,
+				});
+			}
+			false
+		},
+	}
+}
+
+/// Appends the positive or negative list of a node with context
+/// starting at `from_len` (see `branch_counts`); `counts` are the list
+/// lengths of the node for `pathlen == 0`, and `inner` appends the
+/// positive (`true`) or negative list of the node for `pathlen == 0`.
+pub(crate) fn branch_emit<S, F>(key: &S, from_len: usize, positive: bool, counts: (usize, usize), list: &mut Vec<Definition<S>>, inner: F)
+where
+	S: BitString+Clone,
+	F: FnOnce(bool, &mut Vec<Definition<S>>),
+{
+	let pathlen = key.len() - from_len;
+	if 0 == pathlen || positive {
+		return inner(positive, list);
+	}
+	let (pos, neg) = counts;
+	let mut excl_def = Definition{
+		prefix: key.clone(),
+		label: false,
+	};
+	if 1 == pathlen && pos >= neg {
+		excl_def.prefix.flip(from_len);
+		if key.get(from_len) {
+			list.push(excl_def);
+			inner(false, list);
+		} else {
+			inner(false, list);
+			list.push(excl_def);
+		}
+	} else {
+		excl_def.prefix.clip(from_len);
+		list.push(excl_def);
+		if pos < neg + 2 {
+			inner(true, list);
+		} else {
+			list.push(Definition{
+				prefix: key.clone(),
+				label: true,
+			});
+			inner(false, list);
+		}
+	}
+}
+
+// node of the tree in pre-order: the list lengths for `pathlen == 0`,
+// how the lists of the children are combined and the number of nodes
+// in the subtree (only needed by `Parallel`)
+struct CompressNode {
+	counts: (usize, usize),
+	combine: Combine,
+	#[cfg(feature = "parallel")]
+	size: usize,
+}
+
+// how the children of an inner node are handled by `compress_counts`
+// and `compress_inner`
+trait CompressChildren<S: BitString> {
+	// collect `CompressNode`s of both children; returns their list
+	// lengths for a context starting below the node key
+	fn counts(&self, inner: &InnerNode<S>, nodes: &mut Vec<CompressNode>) -> ((usize, usize), (usize, usize));
+
+	// append the lists of both children (with context starting below
+	// the node key) to `list`; `ndx` is the pre-order index of the
+	// left child
+	fn emit(&self, inner: &InnerNode<S>, positive: bool, nodes: &[CompressNode], ndx: &mut usize, list: &mut Vec<Definition<S>>);
+}
+
+// handle the left child, then the right child
+struct Sequential;
+
+impl<S: BitString+Clone> CompressChildren<S> for Sequential {
+	fn counts(&self, inner: &InnerNode<S>, nodes: &mut Vec<CompressNode>) -> ((usize, usize), (usize, usize)) {
+		let from_len = inner.key().len() + 1;
+		let left = Definition::compress_branch_counts(from_len, inner.left(), nodes, self);
+		let right = Definition::compress_branch_counts(from_len, inner.right(), nodes, self);
+		(left, right)
+	}
+
+	fn emit(&self, inner: &InnerNode<S>, positive: bool, nodes: &[CompressNode], ndx: &mut usize, list: &mut Vec<Definition<S>>) {
+		let from_len = inner.key().len() + 1;
+		Definition::compress_branch(from_len, inner.left(), positive, nodes, ndx, list, self);
+		Definition::compress_branch(from_len, inner.right(), positive, nodes, ndx, list, self);
+	}
+}
+
+// handle the right child in a new thread (if more than one thread is
+// left), and split the remaining threads between both children
+#[cfg(feature = "parallel")]
+struct Parallel {
+	threads: usize,
+}
+
+#[cfg(feature = "parallel")]
+impl Parallel {
+	fn split(&self) -> (Parallel, Parallel) {
+		let left = self.threads / 2;
+		(Parallel{ threads: left }, Parallel{ threads: self.threads - left })
+	}
+}
+
+#[cfg(feature = "parallel")]
+impl<S: BitString+Clone+Send+Sync> CompressChildren<S> for Parallel {
+	fn counts(&self, inner: &InnerNode<S>, nodes: &mut Vec<CompressNode>) -> ((usize, usize), (usize, usize)) {
+		if self.threads < 2 {
+			return Sequential.counts(inner, nodes);
+		}
+		let (left_threads, right_threads) = self.split();
+		let from_len = inner.key().len() + 1;
+		thread::scope(|scope| {
+			let right = scope.spawn(|| {
+				let mut right_nodes = Vec::new();
+				let counts = Definition::compress_branch_counts(from_len, inner.right(), &mut right_nodes, &right_threads);
+				(counts, right_nodes)
+			});
+			let left = Definition::compress_branch_counts(from_len, inner.left(), nodes, &left_threads);
+			let (right, right_nodes) = right.join().expect("compress thread panicked");
+			nodes.extend(right_nodes);
+			(left, right)
+		})
+	}
+
+	fn emit(&self, inner: &InnerNode<S>, positive: bool, nodes: &[CompressNode], ndx: &mut usize, list: &mut Vec<Definition<S>>) {
+		if self.threads < 2 {
+			return Sequential.emit(inner, positive, nodes, ndx, list);
+		}
+		let (left_threads, right_threads) = self.split();
+		let from_len = inner.key().len() + 1;
+		let right_ndx = *ndx + nodes[*ndx].size;
+		thread::scope(|scope| {
+			let right = scope.spawn(move || {
+				let mut right_list = Vec::new();
+				let mut ndx = right_ndx;
+				Definition::compress_branch(from_len, inner.right(), positive, nodes, &mut ndx, &mut right_list, &right_threads);
+				right_list
+			});
+			Definition::compress_branch(from_len, inner.left(), positive, nodes, ndx, list, &left_threads);
+			list.extend(right.join().expect("compress thread panicked"));
+		});
+		*ndx = right_ndx + nodes[right_ndx].size;
+	}
 }
 
 impl<S: BitString+Clone> Definition<S> {
-	/// Returns a minimal list of definitions (i.e. (sub-)ranges to
-	/// include and to exclude)
-	///
-	/// The first element of the tuple assumes branch.key[0..from_len]
-	/// is excluded by the parent element, and will therefore start with
-	/// a "positive" (including) definition.  The second element assumes
-	/// branch.key[0..from_len] is already included by the parent
-	/// element, and will start with a "negative" (excluding)
-	/// definition.
-	///
-	/// The second element is an empty iff the branch is a single node
-	/// and `from_len == branch.key().len()`.
-	fn compress_branch(from_len: usize, branch: &Node<S>) -> (Vec<Definition<S>>, Vec<Definition<S>>) {
-		debug_assert!(branch.key().len() >= from_len);
-		match branch.key().len() - from_len {
-			0 => Self::compress_inner(branch),
-			pathlen => {
-				let (pos, mut neg) = Self::compress_inner(branch);
-				let mut excl_def = Definition{
-					prefix: branch.key().clone(),
-					include: false,
+	// collect `CompressNode`s for all nodes (in pre-order), returns
+	// the list lengths of `n`
+	fn compress_counts<C: CompressChildren<S>>(n: &Node<S>, nodes: &mut Vec<CompressNode>, children: &C) -> (usize, usize) {
+		match *n {
+			Node::Leaf(_) => {
+				nodes.push(CompressNode{
+					counts: (1, 0),
+					combine: Combine::Append,
+					#[cfg(feature = "parallel")]
+					size: 1,
+				});
+				(1, 0)
+			},
+			Node::InnerNode(ref inner) => {
+				let ndx = nodes.len();
+				nodes.push(CompressNode{
+					counts: (0, 0),
+					combine: Combine::Append,
+					#[cfg(feature = "parallel")]
+					size: 0,
+				});
+				let ((l_pos, l_neg), (r_pos, r_neg)) = children.counts(inner, nodes);
+				let (combine, counts) = combine_counts(l_pos + r_pos, l_neg + r_neg);
+				nodes[ndx] = CompressNode{
+					counts,
+					combine,
+					#[cfg(feature = "parallel")]
+					size: nodes.len() - ndx,
 				};
-				if 1 == pathlen && pos.len() >= neg.len() {
-					// the next branch would have the same length, but
-					// would exclude a higher level than necessary -
-					// keep excludes longer if possible
-					excl_def.prefix.flip(from_len);
-					if branch.key().get(from_len) {
-						neg.insert(0, excl_def);
-					} else {
-						neg.push(excl_def);
-					}
-				} else {
-					excl_def.prefix.clip(from_len);
-					if pos.len() < neg.len() + 2 {
-						neg.clear();
-						neg.push(excl_def);
-						neg.extend_from_slice(&pos);
-					} else {
-						neg.insert(0, excl_def);
-						neg.insert(1, Definition{
-							prefix: branch.key().clone(),
-							include: true,
-						});
-					}
-				}
-				(pos, neg)
+				counts
 			},
 		}
 	}
 
+	// same as `compress_counts`, but with context starting at
+	// `from_len`
+	fn compress_branch_counts<C: CompressChildren<S>>(from_len: usize, branch: &Node<S>, nodes: &mut Vec<CompressNode>, children: &C) -> (usize, usize) {
+		let (pos, neg) = Self::compress_counts(branch, nodes, children);
+		branch_counts(branch.key().len() - from_len, pos, neg)
+	}
+
+	// append the positive or negative list of `branch` (with context
+	// starting at `from_len`) to `list`; `ndx` is the pre-order index
+	// of `branch` in `nodes`, and is moved past the branch
+	fn compress_branch<C: CompressChildren<S>>(from_len: usize, branch: &Node<S>, positive: bool, nodes: &[CompressNode], ndx: &mut usize, list: &mut Vec<Definition<S>>, children: &C) {
+		let counts = nodes[*ndx].counts;
+		branch_emit(branch.key(), from_len, positive, counts, list, |positive, list| {
+			Self::compress_inner(branch, positive, nodes, ndx, list, children)
+		});
+	}
+
 	/// Same as `compress_branch` but for the special case `from_len =
 	/// branch.key().len()`
-	fn compress_inner(n: &Node<S>) -> (Vec<Definition<S>>, Vec<Definition<S>>) {
-		match *n {
+	fn compress_inner<C: CompressChildren<S>>(n: &Node<S>, positive: bool, nodes: &[CompressNode], ndx: &mut usize, list: &mut Vec<Definition<S>>, children: &C) {
+		let combine = nodes[*ndx].combine;
+		*ndx += 1;
+		let inner = match *n {
 			Node::Leaf(ref leaf) => {
-				(vec!(Definition{
-					prefix: leaf.key().clone(),
-					include: true,
-				}), vec!())
-			},
-			Node::InnerNode(ref inner) => {
-				let from_len = inner.key().len() + 1;
-				let (mut l_pos, mut l_neg) = Self::compress_branch(from_len, inner.left());
-				let (mut r_pos, mut r_neg) = Self::compress_branch(from_len, inner.right());
-				match (l_pos.len() + r_pos.len()) as isize - (l_neg.len() + r_neg.len()) as isize {
-					0 | 1 | -1 => {
-						l_pos.append(&mut r_pos);
-						l_neg.append(&mut r_neg);
-						(l_pos, l_neg)
-					},
-					n if n < 0 => {
-						// negative list is too long
-						l_pos.append(&mut r_pos);
-						l_neg.clear();
-						l_neg.push(Definition{
-							prefix: inner.key().clone(),
-							include: false,
-						});
-						l_neg.extend_from_slice(&l_pos[..]);
-						(l_pos, l_neg)
-					},
-					_ => {
-						// positive list is too long
-						l_neg.append(&mut r_neg);
-						l_pos.clear();
-						l_pos.push(Definition{
-							prefix: inner.key().clone(),
-							include: true,
-						});
-						l_pos.extend_from_slice(&l_neg[..]);
-						(l_pos, l_neg)
-					},
+				if positive {
+					list.push(Definition{
+						prefix: leaf.key().clone(),
+						label: true,
+					});
 				}
+				return;
 			},
-		}
+			Node::InnerNode(ref inner) => inner,
+		};
+		let children_positive = combine_emit(inner.key(), combine, positive, list);
+		children.emit(inner, children_positive, nodes, ndx, list);
 	}
 
-	/// Returns a minimal list of definitions to cover the (possibly
+	// the positive (or negative) list of the root with context starting
+	// at the null prefix
+	fn compress_root<C: CompressChildren<S>>(root: &Node<S>, positive: bool, children: &C) -> Vec<Definition<S>> {
+		let mut nodes = Vec::new();
+		let (pos, neg) = Self::compress_branch_counts(0, root, &mut nodes, children);
+		let mut list = Vec::with_capacity(if positive { pos } else { neg });
+		Self::compress_branch(0, root, positive, &nodes, &mut 0, &mut list, children);
+		list
+	}
+
+	/// Returns a short list of definitions to cover the (possibly
 	/// inverted) set of ranges (i.e. (sub-)ranges to include and to
 	/// exclude); ranges not covered in the result are excluded by
 	/// default.
 	///
 	/// Returned ranges are not prefix free; the longest matching prefix
 	/// for an address specifies whether it is included or excluded.
+	///
+	/// Uses a greedy heuristic; the result is usually, but not always,
+	/// minimal (see `compress_optimal`).
 	pub fn compress(s: &RadixSet<S>, invert: bool) -> Vec<Definition<S>> {
+		Self::compress_with(s, invert, &Sequential)
+	}
+
+	fn compress_with<C: CompressChildren<S>>(s: &RadixSet<S>, invert: bool, children: &C) -> Vec<Definition<S>> {
 		if invert {
 			match s.root() {
-				Some(ref n) => {
-					let mut neg = Self::compress_branch(0, n).1;
-					assert!(neg.is_empty() || !neg[0].include);
+				Some(n) => {
+					let mut neg = Self::compress_root(n, false, children);
+					assert!(neg.is_empty() || !neg[0].label);
 					for def in &mut neg {
-						def.include = !def.include;
+						def.label = !def.label;
 					}
 					neg
 				},
 				None => vec!(Definition{
 					prefix: S::null(),
-					include: true,
+					label: true,
 				}),
 			}
 		} else {
 			match s.root() {
-				Some(ref n) => Self::compress_branch(0, n).0,
+				Some(n) => Self::compress_root(n, true, children),
 				None => vec!(),
 			}
 		}
@@ -146,7 +364,7 @@ impl<S: BitString+Clone> Definition<S> {
 			// use recursion to produce sorted list of definitions
 			let mut excl_def = Definition{
 				prefix: branch_key.clone(),
-				include: invert,
+				label: invert,
 			};
 			excl_def.prefix.flip(from_len);
 			excl_def.prefix.clip(from_len + 1);
@@ -170,7 +388,7 @@ impl<S: BitString+Clone> Definition<S> {
 			Node::Leaf(ref leaf) => {
 				list.push(Definition{
 					prefix: leaf.key().clone(),
-					include: !invert,
+					label: !invert,
 				});
 			},
 			Node::InnerNode(ref inner) => {
@@ -189,22 +407,684 @@ impl<S: BitString+Clone> Definition<S> {
 	/// addresses.
 	pub fn complete(s: &RadixSet<S>, invert: bool) -> Vec<Definition<S>> {
 		match s.root() {
-			Some(ref n) => {
+			Some(n) => {
 				let mut list : Vec<Definition<S>> = vec!();
 				Self::complete_branch(0, n, &mut list, invert);
 				list
 			},
 			None => vec!(Definition{
 				prefix: S::null(),
-				include: invert,
+				label: invert,
+			}),
+		}
+	}
+}
+
+#[cfg(feature = "parallel")]
+impl<S: BitString+Clone+Send+Sync> Definition<S> {
+	/// Same as `compress` (the result is the same), but uses up to
+	/// `threads` threads: the subtrees below the upper levels of the
+	/// tree are handled in parallel.
+	///
+	/// Only available with the `parallel` feature.
+	pub fn compress_parallel(s: &RadixSet<S>, invert: bool, threads: usize) -> Vec<Definition<S>> {
+		Self::compress_with(s, invert, &Parallel{ threads })
+	}
+}
+
+// minimal cost of the definitions for a sub tree, depending on
+// whether the node prefix is excluded (`cost.0`) or included
+// (`cost.1`) by the parent definitions
+struct OptimalTree<'a, S: BitString+'a> {
+	node: &'a Node<S>,
+	cost: (u64, u64),
+	children: Option<Box<(OptimalTree<'a, S>, OptimalTree<'a, S>)>>,
+}
+
+// prefix `key[0..len]`
+fn clipped<S: BitString+Clone>(key: &S, len: usize) -> S {
+	let mut prefix = key.clone();
+	prefix.clip(len);
+	prefix
+}
+
+// neighbour of the prefix `key[0..len+1]`
+fn gap<S: BitString+Clone>(key: &S, len: usize) -> S {
+	let mut gap = clipped(key, len + 1);
+	gap.flip(len);
+	gap
+}
+
+// cheapest choice given whether the prefix is included; optionally a
+// definition for the prefix switches the context
+pub(crate) fn switch_cost<S, F>(prefix: &S, keep: (u64, u64), cost: &F) -> (u64, u64)
+where
+	F: Fn(&S, bool) -> u64,
+{
+	(
+		min(keep.0, cost(prefix, true).saturating_add(keep.1)),
+		min(keep.1, cost(prefix, false).saturating_add(keep.0)),
+	)
+}
+
+impl<'a, S: BitString+Clone+'a> OptimalTree<'a, S> {
+	fn new<F: Fn(&S, bool) -> u64>(node: &'a Node<S>, cost: &F) -> Self {
+		match *node {
+			Node::Leaf(ref leaf) => OptimalTree{
+				node,
+				cost: (cost(leaf.key(), true), 0),
+				children: None,
+			},
+			Node::InnerNode(ref inner) => {
+				let from_len = inner.key().len() + 1;
+				let left = OptimalTree::new(inner.left(), cost);
+				let right = OptimalTree::new(inner.right(), cost);
+				let (l_pos, l_neg) = left.branch_cost(from_len, cost);
+				let (r_pos, r_neg) = right.branch_cost(from_len, cost);
+				let keep = (l_pos.saturating_add(r_pos), l_neg.saturating_add(r_neg));
+				OptimalTree{
+					node,
+					cost: switch_cost(inner.key(), keep, cost),
+					children: Some(Box::new((left, right))),
+				}
+			},
+		}
+	}
+
+	// costs for the regions `key[0..d]` from `d = key.len()` up to
+	// `d = from_len` (last entry): if a region is included the
+	// neighbour of the path needs to be excluded
+	fn path_costs<F: Fn(&S, bool) -> u64>(&self, from_len: usize, cost: &F) -> Vec<(u64, u64)> {
+		let key = self.node.key();
+		let mut list = vec![self.cost];
+		for d in (from_len..key.len()).rev() {
+			let below = list[list.len() - 1];
+			let keep = (below.0, below.1.saturating_add(cost(&gap(key, d), false)));
+			list.push(switch_cost(&clipped(key, d), keep, cost));
+		}
+		list
+	}
+
+	// costs for the region `key[0..from_len]` containing the sub tree
+	fn branch_cost<F: Fn(&S, bool) -> u64>(&self, from_len: usize, cost: &F) -> (u64, u64) {
+		let list = self.path_costs(from_len, cost);
+		list[list.len() - 1]
+	}
+
+	fn emit_node<F: Fn(&S, bool) -> u64>(&self, include: bool, cost: &F, list: &mut Vec<Definition<S>>) {
+		let key = self.node.key();
+		match self.children {
+			None => {
+				if !include {
+					list.push(Definition{
+						prefix: key.clone(),
+						label: true,
+					});
+				}
+			},
+			Some(ref children) => {
+				let from_len = key.len() + 1;
+				let (ref left, ref right) = **children;
+				let (l_pos, l_neg) = left.branch_cost(from_len, cost);
+				let (r_pos, r_neg) = right.branch_cost(from_len, cost);
+				let (keep, target) = if include {
+					(l_neg.saturating_add(r_neg), self.cost.1)
+				} else {
+					(l_pos.saturating_add(r_pos), self.cost.0)
+				};
+				let include = if keep == target {
+					include
+				} else {
+					list.push(Definition{
+						prefix: key.clone(),
+						label: !include,
+					});
+					!include
+				};
+				left.emit_branch(from_len, include, cost, list);
+				right.emit_branch(from_len, include, cost, list);
+			},
+		}
+	}
+
+	fn emit_branch<F: Fn(&S, bool) -> u64>(&self, from_len: usize, include: bool, cost: &F, list: &mut Vec<Definition<S>>) {
+		let key = self.node.key();
+		let costs = self.path_costs(from_len, cost);
+		let at = |d: usize, include: bool| {
+			let c = costs[key.len() - d];
+			if include { c.1 } else { c.0 }
+		};
+		let mut include = include;
+		// excluded neighbours after the sub tree (in lexicographic
+		// order)
+		let mut after = Vec::new();
+		for d in from_len..key.len() {
+			let target = at(d, include);
+			let mut keep = at(d + 1, include);
+			if include {
+				keep = keep.saturating_add(cost(&gap(key, d), false));
+			}
+			// prefer not to switch context (keeps excludes longer)
+			if keep != target {
+				list.push(Definition{
+					prefix: clipped(key, d),
+					label: !include,
+				});
+				include = !include;
+			}
+			if include {
+				let def = Definition{
+					prefix: gap(key, d),
+					label: false,
+				};
+				if key.get(d) {
+					list.push(def);
+				} else {
+					after.push(def);
+				}
+			}
+		}
+		self.emit_node(include, cost, list);
+		after.reverse();
+		list.append(&mut after);
+	}
+}
+
+/// Costs of definitions for `Definition::compress_costs`
+///
+/// A definition can cost more than two definitions of the halves of
+/// its prefix (e.g. if `per_length` is high for a single length);
+/// `compress_costs` splits such definitions.
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct RuleCosts {
+	/// cost of an including definition
+	pub include: u64,
+	/// cost of an excluding definition
+	pub exclude: u64,
+	/// additional cost of a definition by network length (missing
+	/// entries cost nothing)
+	pub per_length: Vec<u64>,
+}
+
+impl Default for RuleCosts {
+	/// every definition costs 1
+	fn default() -> Self {
+		RuleCosts{
+			include: 1,
+			exclude: 1,
+			per_length: Vec::new(),
+		}
+	}
+}
+
+impl RuleCosts {
+	/// Cost of a definition for `prefix` including or excluding it
+	pub fn cost<S: IpPrefix>(&self, prefix: &S, include: bool) -> u64 {
+		let base = if include { self.include } else { self.exclude };
+		let extra = prefix.prefix_network_length().and_then(|l| self.per_length.get(l)).cloned().unwrap_or(0);
+		base.saturating_add(extra)
+	}
+
+	/// Minimal cost of including or excluding all addresses of
+	/// `prefix` with definitions for `prefix` or longer prefixes
+	/// within it (i.e. `cost` unless splitting the prefix is cheaper)
+	pub fn split_cost<S: IpPrefix>(&self, prefix: &S, include: bool) -> u64 {
+		let (len, address_length) = match (prefix.prefix_network_length(), prefix.prefix_address_length()) {
+			(Some(len), Some(address_length)) => (len, address_length),
+			_ => return self.cost(prefix, include),
+		};
+		let base = if include { self.include } else { self.exclude };
+		let at = |l: usize| base.saturating_add(self.per_length.get(l).cloned().unwrap_or(0));
+		// longer prefixes without entry in `per_length` never split
+		let start = min(address_length, self.per_length.len());
+		let mut cost = at(start);
+		for l in (len..start).rev() {
+			cost = min(at(l), cost.saturating_mul(2));
+		}
+		cost
+	}
+
+	// split `def` into definitions for its halves while that is
+	// cheaper; skips halves with a definition in `defs` (sorted
+	// lexicographically)
+	fn split<S: IpPrefix>(&self, def: Definition<S>, defs: &[Definition<S>], list: &mut Vec<Definition<S>>) {
+		if self.cost(&def.prefix, def.label) <= self.split_cost(&def.prefix, def.label) {
+			list.push(def);
+			return;
+		}
+		for &bit in &[false, true] {
+			let mut prefix = def.prefix.clone();
+			prefix.append(bit);
+			if defs.binary_search_by(|d| d.prefix.lexicographic_cmp(&prefix)).is_err() {
+				self.split(Definition{ prefix, label: def.label }, defs, list);
+			}
+		}
+	}
+}
+
+impl<S: BitString+Clone> Definition<S> {
+	/// Same as `compress`, but minimises the total cost of the
+	/// definitions instead of their number; `cost` gets the prefix of
+	/// a definition and whether it includes the prefix.
+	///
+	/// On equal costs excludes are kept longer (like `compress`).
+	///
+	/// Only prefixes on the paths to the prefixes of the set and their
+	/// neighbours are considered; the result isn't minimal if a
+	/// definition can cost more than definitions for both halves of its
+	/// prefix (see `compress_costs`).
+	pub fn compress_weighted<F>(s: &RadixSet<S>, invert: bool, cost: F) -> Vec<Definition<S>>
+	where
+		F: Fn(&S, bool) -> u64,
+	{
+		let root = match s.root() {
+			Some(n) => n,
+			None => return Self::compress(s, invert),
+		};
+		let mut list = Vec::new();
+		if invert {
+			// assume everything is included, and invert the resulting
+			// definitions (and the costs)
+			let cost = |prefix: &S, include: bool| cost(prefix, !include);
+			OptimalTree::new(root, &cost).emit_branch(0, true, &cost, &mut list);
+			for def in &mut list {
+				def.label = !def.label;
+			}
+		} else {
+			OptimalTree::new(root, &cost).emit_branch(0, false, &cost, &mut list);
+		}
+		list
+	}
+
+	/// Same as `compress_weighted` with the costs of `costs`; the total
+	/// cost of the result is minimal.
+	///
+	/// Definitions are only searched on the paths to the prefixes of
+	/// the set and their neighbours; definitions which cost more than
+	/// covering both halves of their prefix are split afterwards.
+	pub fn compress_costs(s: &RadixSet<S>, invert: bool, costs: &RuleCosts) -> Vec<Definition<S>>
+	where
+		S: IpPrefix,
+	{
+		let defs = Self::compress_weighted(s, invert, |prefix, include| costs.split_cost(prefix, include));
+		let mut list = Vec::with_capacity(defs.len());
+		for def in defs.iter().cloned() {
+			costs.split(def, &defs, &mut list);
+		}
+		list.sort_by(|a, b| a.prefix.lexicographic_cmp(&b.prefix));
+		list
+	}
+
+	/// Same as `compress`, but the result is guaranteed to be minimal
+	/// (it is built from the minimal number of definitions for each
+	/// sub tree, calculated bottom-up).
+	pub fn compress_optimal(s: &RadixSet<S>, invert: bool) -> Vec<Definition<S>> {
+		Self::compress_weighted(s, invert, |_, _| 1)
+	}
+
+	/// Minimal number of definitions needed to cover the (possibly
+	/// inverted) set, i.e. the length of `compress_optimal`.
+	pub fn optimal_len(s: &RadixSet<S>, invert: bool) -> usize {
+		let cost = |_: &S, _: bool| 1;
+		match s.root() {
+			Some(n) => {
+				let cost = OptimalTree::new(n, &cost).branch_cost(0, &cost);
+				(if invert { cost.1 } else { cost.0 }) as usize
+			},
+			None => if invert { 1 } else { 0 },
+		}
+	}
+}
+
+// ORTC: labels a sub tree could get with a minimal number of rules;
+// `None` if all labels are fine (don't care)
+struct LabelTree<L> {
+	labels: Option<BTreeSet<L>>,
+	children: Option<Box<(LabelTree<L>, LabelTree<L>)>>,
+}
+
+impl<L: Ord+Clone> LabelTree<L> {
+	// build from a complete (sorted) partition of the prefix of length
+	// `len` into regions with a label (`None`: don't care)
+	fn new<S: BitString>(len: usize, regions: &[(S, Option<L>)]) -> Self {
+		if 1 == regions.len() {
+			debug_assert_eq!(len, regions[0].0.len());
+			return LabelTree{
+				labels: regions[0].1.as_ref().map(|l| Some(l.clone()).into_iter().collect()),
+				children: None,
+			};
+		}
+		let split = regions.iter().position(|r| r.0.get(len)).expect("incomplete partition");
+		let left = Self::new(len + 1, &regions[..split]);
+		let right = Self::new(len + 1, &regions[split..]);
+		let labels = match (left.labels.as_ref(), right.labels.as_ref()) {
+			(None, None) => None,
+			(Some(l), None) => Some(l.clone()),
+			(None, Some(r)) => Some(r.clone()),
+			(Some(l), Some(r)) => {
+				let shared : BTreeSet<L> = l.intersection(r).cloned().collect();
+				if shared.is_empty() {
+					Some(l.union(r).cloned().collect())
+				} else {
+					Some(shared)
+				}
+			},
+		};
+		LabelTree{
+			labels,
+			children: Some(Box::new((left, right))),
+		}
+	}
+
+	// add rules for all nodes which can't keep the label of the parent
+	fn emit<S: BitString+Clone>(&self, prefix: S, parent: &L, list: &mut Vec<Definition<S, L>>) {
+		let labels = match self.labels {
+			None => return,
+			Some(ref labels) => labels,
+		};
+		let label = if labels.contains(parent) {
+			parent
+		} else {
+			let label = labels.iter().next().expect("empty label set");
+			list.push(Definition{
+				prefix: prefix.clone(),
+				label: label.clone(),
+			});
+			label
+		};
+		if let Some(ref children) = self.children {
+			let mut left = prefix.clone();
+			left.append(false);
+			children.0.emit(left, label, list);
+			let mut right = prefix;
+			right.append(true);
+			children.1.emit(right, label, list);
+		}
+	}
+}
+
+impl<S: BitString+Clone, L: Ord+Clone> Definition<S, L> {
+	// minimal list of definitions for a complete (sorted) partition of
+	// the address space; addresses not covered get `default`
+	fn compress_regions(regions: &[(S, Option<L>)], default: &L) -> Vec<Definition<S, L>> {
+		let mut list = Vec::new();
+		LabelTree::new(0, regions).emit(S::null(), default, &mut list);
+		list
+	}
+
+	/// Returns a minimal list of definitions (longest matching prefix
+	/// wins) mapping all addresses to the same label as `map`;
+	/// addresses not in `map` get `default`, and addresses not covered
+	/// by the result get `default` too.
+	///
+	/// Uses the ORTC (optimal routing table constructor) algorithm.
+	pub fn compress_labels(map: &RadixMap<S, L>, default: &L) -> Vec<Definition<S, L>> {
+		let regions : Vec<(S, Option<L>)> = map.iter_full()
+			.map(|(key, label)| (key, Some(label.unwrap_or(default).clone())))
+			.collect();
+		Self::compress_regions(&regions, default)
+	}
+}
+
+impl<S: BitString+Clone> Definition<S> {
+	/// Returns a minimal list of definitions which include all
+	/// addresses of the (possibly inverted) set `s` and exclude all
+	/// other addresses, apart from the addresses in `dont_care`: those
+	/// may be included or excluded, whatever results in fewer
+	/// definitions.
+	///
+	/// Addresses in `dont_care` are "don't care" even if they are in
+	/// `s`.  Same semantics as `compress`: the longest matching prefix
+	/// decides whether an address is included, addresses not covered
+	/// are excluded.
+	pub fn compress_dont_care(s: &RadixSet<S>, dont_care: &RadixSet<S>, invert: bool) -> Vec<Definition<S>> {
+		let mut regions = Vec::new();
+		set_ops::walk(s, dont_care, |key, in_s, in_dont_care| {
+			let label = if in_dont_care { None } else { Some(in_s != invert) };
+			regions.push((key.clone(), label));
+		});
+		Self::compress_regions(&regions, &false)
+	}
+}
+
+/// Result of looking up a prefix in a list of definitions
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub enum Lookup<'a, S: BitString+'a> {
+	/// no definition matches the prefix (or a more specific prefix);
+	/// it is excluded by default
+	Default,
+	/// the longest matching definition decides for the complete prefix
+	Match(&'a Definition<S>),
+	/// more specific definitions override the longest matching
+	/// definition (or the default) for the complete prefix; all
+	/// addresses are included (`true`) or excluded (`false`)
+	Covered(bool),
+	/// more specific definitions include some, but not all, addresses
+	/// of the prefix; contains the longest matching definition (if any)
+	Partial(Option<&'a Definition<S>>),
+}
+
+impl<'a, S: BitString+'a> Lookup<'a, S> {
+	/// Whether the prefix is completely included (`Some(true)`),
+	/// completely excluded (`Some(false)`) or partially included
+	/// (`None`)
+	pub fn included(&self) -> Option<bool> {
+		match *self {
+			Lookup::Default => Some(false),
+			Lookup::Match(def) => Some(def.label),
+			Lookup::Covered(label) => Some(label),
+			Lookup::Partial(_) => None,
+		}
+	}
+}
+
+impl<S: BitString+Clone> Definition<S> {
+	/// Evaluate a list of definitions (longest matching prefix wins)
+	/// for all addresses in `key`.
+	///
+	/// If there are multiple definitions for the same prefix the first
+	/// one is used.  More specific definitions only make a difference
+	/// if they actually change the result (redundant or overridden
+	/// definitions don't).
+	///
+	/// Runs in `O(n log n)` time in the length of the list.
+	pub fn lookup<'a>(defs: &'a [Definition<S>], key: &S) -> Lookup<'a, S> {
+		let key_len = key.len();
+		let mut matched : Option<&'a Definition<S>> = None;
+		// the key decides the result for the covered sub-tree, followed
+		// by the more specific definitions
+		let mut covered = vec![Definition{
+			prefix: key.clone(),
+			label: false,
+		}];
+		for def in defs {
+			let def_len = def.prefix.len();
+			if def_len <= key_len && def_len == def.prefix.shared_prefix_len(key) {
+				let longer = match matched {
+					None => true,
+					Some(m) => def_len > m.prefix.len(),
+				};
+				if longer {
+					matched = Some(def);
+				}
+			} else if def_len > key_len && key_len == def.prefix.shared_prefix_len(key) {
+				covered.push(def.clone());
+			}
+		}
+		let include = matched.is_some_and(|m| m.label);
+		covered[0].label = include;
+		// more specific definitions could change the result for (parts
+		// of) the prefix
+		let evaluated = Self::evaluate(&covered);
+		let result = match evaluated.iter().next() {
+			None => Some(false),
+			Some(p) if p.len() == key_len => Some(true),
+			Some(_) => None,
+		};
+		match (result, matched) {
+			(None, _) => Lookup::Partial(matched),
+			(Some(label), _) if label != include => Lookup::Covered(label),
+			(Some(_), None) => Lookup::Default,
+			(Some(_), Some(m)) => Lookup::Match(m),
+		}
+	}
+
+	/// Evaluates a list of definitions (longest matching prefix wins,
+	/// everything else is excluded) into the set of included
+	/// addresses.
+	///
+	/// The list may contain redundant and overlapping definitions; if
+	/// there are multiple definitions for the same prefix the first
+	/// one is used (same as `lookup`).
+	pub fn evaluate(defs: &[Definition<S>]) -> RadixSet<S> {
+		let mut sorted : Vec<&Definition<S>> = defs.iter().collect();
+		// stable sort: duplicates stay in original order
+		sorted.sort_by(|a, b| {
+			a.prefix.len().cmp(&b.prefix.len()).then_with(|| a.prefix.lexicographic_cmp(&b.prefix))
+		});
+		sorted.dedup_by(|b, a| a.prefix.len() == b.prefix.len() && a.prefix.lexicographic_cmp(&b.prefix).is_eq());
+
+		// prefixes of the same length are disjoint; longer prefixes
+		// override shorter ones
+		let mut result = RadixSet::default();
+		let mut ndx = 0;
+		while ndx < sorted.len() {
+			let len = sorted[ndx].prefix.len();
+			let mut include = RadixSet::default();
+			let mut exclude = RadixSet::default();
+			while ndx < sorted.len() && sorted[ndx].prefix.len() == len {
+				let def = sorted[ndx];
+				if def.label {
+					include.insert(def.prefix.clone());
+				} else {
+					exclude.insert(def.prefix.clone());
+				}
+				ndx += 1;
+			}
+			if exclude.root().is_some() {
+				result = set_ops::difference(&result, &exclude);
+			}
+			if include.root().is_some() {
+				result = set_ops::union(&result, &include);
+			}
+		}
+		result
+	}
+}
+
+/// A prefix for which a list of definitions doesn't match the expected
+/// set
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct Mismatch<S> {
+	/// the (maximal) prefix with the wrong result
+	pub prefix: S,
+	/// whether the definitions include the prefix (and shouldn't), or
+	/// exclude it (and shouldn't)
+	pub included: bool,
+}
+
+impl<S: fmt::Display> fmt::Display for Mismatch<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.included {
+			write!(f, "{} is included but shouldn't be", self.prefix)
+		} else {
+			write!(f, "{} is excluded but shouldn't be", self.prefix)
+		}
+	}
+}
+
+impl<S: BitString+Clone> Definition<S> {
+	/// Checks whether the definitions cover exactly the (possibly
+	/// inverted) set, i.e. whether `defs` are a correct result of
+	/// `compress` or `complete` for `s`.
+	///
+	/// Returns the first (in lexicographic order) differing prefix
+	/// otherwise.
+	pub fn verify(defs: &[Definition<S>], s: &RadixSet<S>, invert: bool) -> Result<(), Mismatch<S>> {
+		Self::verify_dont_care(defs, s, &RadixSet::default(), invert)
+	}
+
+	/// Same as `verify`, but ignores addresses in `dont_care`, i.e.
+	/// whether `defs` are a correct result of `compress_dont_care`.
+	pub fn verify_dont_care(defs: &[Definition<S>], s: &RadixSet<S>, dont_care: &RadixSet<S>, invert: bool) -> Result<(), Mismatch<S>> {
+		let evaluated = Self::evaluate(defs);
+		let mut diff = set_ops::combine(&evaluated, s, |e, x| e != (x != invert));
+		if dont_care.root().is_some() {
+			diff = set_ops::difference(&diff, dont_care);
+		}
+		match diff.iter().next() {
+			None => Ok(()),
+			Some(prefix) => Err(Mismatch{
+				prefix: prefix.clone(),
+				included: set_ops::contains(&evaluated, prefix) == Some(true),
 			}),
 		}
 	}
 }
 
+/// Error returned when parsing a definition
+#[derive(Clone,PartialEq,Eq)]
+pub enum ParseDefinitionError<E> {
+	/// line didn't consist of exactly two words
+	InvalidSyntax,
+	/// first word wasn't `include` or `exclude`
+	UnknownKeyword(String),
+	/// failed to parse the prefix
+	Prefix(E),
+}
+
+impl<E: fmt::Debug> fmt::Debug for ParseDefinitionError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ParseDefinitionError::InvalidSyntax => {
+				write!(f, "expected \"include PREFIX\" or \"exclude PREFIX\"")
+			},
+			ParseDefinitionError::UnknownKeyword(ref k) => {
+				write!(f, "unknown keyword {:?} (expected \"include\" or \"exclude\")", k)
+			},
+			ParseDefinitionError::Prefix(ref e) => fmt::Debug::fmt(e, f),
+		}
+	}
+}
+
+impl<E: fmt::Debug> fmt::Display for ParseDefinitionError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(self, f)
+	}
+}
+
+impl<E: fmt::Debug> Error for ParseDefinitionError<E> {
+}
+
+/// Parses the format generated by `Display`: `include PREFIX` or
+/// `exclude PREFIX`.
+impl<S> FromStr for Definition<S>
+where
+	S: BitString+FromStr,
+{
+	type Err = ParseDefinitionError<S::Err>;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut words = s.split_whitespace();
+		let (keyword, prefix) = match (words.next(), words.next(), words.next()) {
+			(Some(k), Some(p), None) => (k, p),
+			_ => return Err(ParseDefinitionError::InvalidSyntax),
+		};
+		let include = match keyword {
+			"include" => true,
+			"exclude" => false,
+			_ => return Err(ParseDefinitionError::UnknownKeyword(keyword.to_string())),
+		};
+		Ok(Definition{
+			prefix: S::from_str(prefix).map_err(ParseDefinitionError::Prefix)?,
+			label: include,
+		})
+	}
+}
+
 impl<S: BitString+fmt::Debug> fmt::Debug for Definition<S> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		if self.include {
+		if self.label {
 			write!(f, "include {:?}", self.prefix)
 		} else {
 			write!(f, "exclude {:?}", self.prefix)
@@ -214,7 +1094,7 @@ impl<S: BitString+fmt::Debug> fmt::Debug for Definition<S> {
 
 impl<S: BitString+fmt::Display> fmt::Display for Definition<S> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		if self.include {
+		if self.label {
 			write!(f, "include {}", self.prefix)
 		} else {
 			write!(f, "exclude {}", self.prefix)
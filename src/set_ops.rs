@@ -0,0 +1,162 @@
+//! set algebra on `RadixSet`s
+use bitstring::BitString;
+use bitstring_trees::set::{RadixSet,Node};
+
+/// Binary operation on sets
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum SetOperation {
+	/// all prefixes in at least one of the sets
+	Union,
+	/// all prefixes in both sets
+	Intersection,
+	/// all prefixes in the first but not in the second set
+	Difference,
+	/// all prefixes in exactly one of the sets
+	SymmetricDifference,
+}
+
+impl SetOperation {
+	/// Whether an address is in the result given whether it is in the
+	/// first and in the second set.
+	pub fn contains(self, a: bool, b: bool) -> bool {
+		match self {
+			SetOperation::Union => a || b,
+			SetOperation::Intersection => a && b,
+			SetOperation::Difference => a && !b,
+			SetOperation::SymmetricDifference => a != b,
+		}
+	}
+
+	/// Apply operation to two sets
+	pub fn apply<S: BitString+Clone>(self, a: &RadixSet<S>, b: &RadixSet<S>) -> RadixSet<S> {
+		combine(a, b, |x, y| self.contains(x, y))
+	}
+
+	/// Apply operation from left to right to a list of sets, i.e.
+	/// `((s1 op s2) op s3) ...`; returns an empty set for an empty
+	/// list.
+	pub fn fold<S, I>(self, sets: I) -> RadixSet<S>
+	where
+		S: BitString+Clone,
+		I: IntoIterator<Item=RadixSet<S>>,
+	{
+		let mut sets = sets.into_iter();
+		let first = match sets.next() {
+			Some(s) => s,
+			None => return RadixSet::default(),
+		};
+		sets.fold(first, |acc, s| self.apply(&acc, &s))
+	}
+}
+
+// whether `key` is the last prefix of its length below `key[0..from_len]`
+fn is_last_below<S: BitString>(key: &S, from_len: usize) -> bool {
+	(from_len..key.len()).all(|i| key.get(i))
+}
+
+/// Calls `f` for each prefix of the (ordered) complete partition of
+/// the address space into prefixes which are either completely in or
+/// completely out of `a`, and the same for `b`; passes whether the
+/// prefix is in `a` and whether it is in `b`.
+///
+/// Walks the (ordered) complete coverage of both sets in parallel.
+pub fn walk<S, F>(a: &RadixSet<S>, b: &RadixSet<S>, mut f: F)
+where
+	S: BitString+Clone,
+	F: FnMut(&S, bool, bool),
+{
+	let mut iter_a = a.iter_full();
+	let mut iter_b = b.iter_full();
+	let mut cur_a = iter_a.next();
+	let mut cur_b = iter_b.next();
+
+	// both lists cover the same space in the same order; therefore the
+	// current elements always start at the same address, and one of
+	// them is a prefix of the other.
+	while let (Some((key_a, in_a)), Some((key_b, in_b))) = (cur_a.clone(), cur_b.clone()) {
+		if key_a.len() <= key_b.len() {
+			debug_assert_eq!(key_a.len(), key_a.shared_prefix_len(&key_b));
+			f(&key_b, in_a, in_b);
+			if is_last_below(&key_b, key_a.len()) {
+				cur_a = iter_a.next();
+			}
+			cur_b = iter_b.next();
+		} else {
+			debug_assert_eq!(key_b.len(), key_b.shared_prefix_len(&key_a));
+			f(&key_a, in_a, in_b);
+			if is_last_below(&key_a, key_b.len()) {
+				cur_b = iter_b.next();
+			}
+			cur_a = iter_a.next();
+		}
+	}
+	debug_assert!(cur_a.is_none() && cur_b.is_none());
+}
+
+/// Builds a new set containing all addresses for which `f` returns true
+/// given whether the address is in `a` and whether it is in `b`.
+pub fn combine<S, F>(a: &RadixSet<S>, b: &RadixSet<S>, f: F) -> RadixSet<S>
+where
+	S: BitString+Clone,
+	F: Fn(bool, bool) -> bool,
+{
+	let mut result = RadixSet::default();
+	walk(a, b, |key, in_a, in_b| {
+		if f(in_a, in_b) {
+			result.insert(key.clone());
+		}
+	});
+	result
+}
+
+/// All addresses in `a` or `b`
+pub fn union<S: BitString+Clone>(a: &RadixSet<S>, b: &RadixSet<S>) -> RadixSet<S> {
+	SetOperation::Union.apply(a, b)
+}
+
+/// All addresses in both `a` and `b`
+pub fn intersection<S: BitString+Clone>(a: &RadixSet<S>, b: &RadixSet<S>) -> RadixSet<S> {
+	SetOperation::Intersection.apply(a, b)
+}
+
+/// All addresses in `a` but not in `b`
+pub fn difference<S: BitString+Clone>(a: &RadixSet<S>, b: &RadixSet<S>) -> RadixSet<S> {
+	SetOperation::Difference.apply(a, b)
+}
+
+/// All addresses in exactly one of `a` and `b`
+pub fn symmetric_difference<S: BitString+Clone>(a: &RadixSet<S>, b: &RadixSet<S>) -> RadixSet<S> {
+	SetOperation::SymmetricDifference.apply(a, b)
+}
+
+/// Whether all addresses in `key` are in the set (`Some(true)`), none
+/// of them (`Some(false)`), or only some of them (`None`).
+pub fn contains<S: BitString+Clone>(set: &RadixSet<S>, key: &S) -> Option<bool> {
+	let mut node = match set.root() {
+		None => return Some(false),
+		Some(n) => n,
+	};
+	loop {
+		let node_key = node.key();
+		let shared = node_key.shared_prefix_len(key);
+		if shared < node_key.len() {
+			if shared == key.len() {
+				// node is a real subset of key
+				return None;
+			}
+			// disjoint
+			return Some(false);
+		}
+		// node_key is a prefix of key
+		match *node {
+			Node::Leaf(_) => return Some(true),
+			Node::InnerNode(ref inner) => {
+				if key.len() == node_key.len() {
+					// inner nodes are never complete
+					return None;
+				}
+				node = if key.get(node_key.len()) { inner.right() } else { inner.left() };
+			},
+		}
+	}
+}
@@ -0,0 +1,102 @@
+//! size statistics of a set
+use bitstring::BitString;
+use bitstring_trees::set::{Node,RadixSet};
+use prefix::IpPrefix;
+use std::mem;
+
+/// Statistics of the prefixes of one address family in a set
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct FamilyStats {
+	/// number of prefixes
+	pub prefixes: usize,
+	/// number of addresses covered by the prefixes; saturates at
+	/// `u128::MAX` (i.e. `::/0` is counted as `2^128 - 1` addresses)
+	pub addresses: u128,
+	/// number of prefixes by network length (the index)
+	pub lengths: Vec<usize>,
+}
+
+impl FamilyStats {
+	fn new(address_length: usize) -> Self {
+		FamilyStats{
+			prefixes: 0,
+			addresses: 0,
+			lengths: vec![0; address_length + 1],
+		}
+	}
+
+	fn add(&mut self, network_length: usize) {
+		let host_bits = self.lengths.len() - 1 - network_length;
+		self.prefixes += 1;
+		self.addresses = self.addresses.saturating_add(if host_bits < 128 {
+			1u128 << host_bits
+		} else {
+			u128::MAX
+		});
+		self.lengths[network_length] += 1;
+	}
+}
+
+/// Statistics of a set (see `SetStats::new`)
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct SetStats {
+	/// IPv4 prefixes
+	pub ipv4: FamilyStats,
+	/// IPv6 prefixes
+	pub ipv6: FamilyStats,
+	/// number of IPv6 `/64` networks containing addresses of the set
+	/// (i.e. the IPv6 address count in `/64` units, rounded up per
+	/// network)
+	pub ipv6_networks: u128,
+}
+
+impl SetStats {
+	/// Collects statistics of the (non-overlapping) prefixes of the
+	/// set; the whole address space (`AnyIpCidr::Any`) is counted as
+	/// `0.0.0.0/0` and `::/0`.
+	pub fn new<S: IpPrefix>(set: &RadixSet<S>) -> Self {
+		let mut stats = SetStats{
+			ipv4: FamilyStats::new(32),
+			ipv6: FamilyStats::new(128),
+			ipv6_networks: 0,
+		};
+		// last `/64` network counted for a longer prefix
+		let mut last_network: Option<S> = None;
+		for prefix in set.iter() {
+			let network_length = match prefix.prefix_network_length() {
+				Some(l) => l,
+				None => {
+					stats.ipv4.add(0);
+					stats.ipv6.add(0);
+					stats.ipv6_networks += 1 << 64;
+					continue;
+				},
+			};
+			if Some(32) == prefix.prefix_address_length() {
+				stats.ipv4.add(network_length);
+				continue;
+			}
+			stats.ipv6.add(network_length);
+			if network_length <= 64 {
+				stats.ipv6_networks += 1 << (64 - network_length);
+			} else {
+				let mut network = prefix.clone();
+				network.clip(S::FAMILY_BITS + 64);
+				if last_network.as_ref().is_none_or(|last| !last.lexicographic_cmp(&network).is_eq()) {
+					stats.ipv6_networks += 1;
+					last_network = Some(network);
+				}
+			}
+		}
+		stats
+	}
+}
+
+/// Estimated memory used by the nodes of the set (in bytes): a set with
+/// `n` prefixes has `2n - 1` nodes.
+pub fn estimated_memory<S: BitString+Clone>(set: &RadixSet<S>) -> usize {
+	match set.iter().count() {
+		0 => 0,
+		prefixes => (2 * prefixes - 1) * mem::size_of::<Node<S>>(),
+	}
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::useless_concat)]
+
 use cidr::Ipv4Cidr;
 use std::str::FromStr;
 use bitstring_trees::set::RadixSet;
@@ -877,3 +879,1677 @@ fn complete_ipv4_single_lastbit_one() {
 		)
 	);
 }
+
+// tests for input
+
+#[test]
+fn read_ipv4_skips_comments() {
+	let input = "# comment\n\n10.0.0.0/8\n10.1.0.0/16\n192.168.0.0/16\n";
+	let rs : RadixSet<Ipv4Cidr> = ::input::read(input.as_bytes()).unwrap();
+
+	assert_eq!(
+		format_lines(rs.iter().collect::<Vec<_>>()),
+		concat!(
+			"10.0.0.0/8\n",
+			"192.168.0.0/16\n",
+		)
+	);
+}
+
+#[test]
+fn read_ipv4_strict_error_location() {
+	let input = "10.0.0.0/8\n  10.1.2.3/16\n";
+	let err = ::input::read::<Ipv4Cidr, _>(input.as_bytes()).unwrap_err();
+
+	match err {
+		::input::ReadError::Parse(e) => {
+			assert_eq!(e.source, "<input>");
+			assert_eq!(e.line, 2);
+			assert_eq!(e.column, 3);
+			assert_eq!(e.text, "10.1.2.3/16");
+		},
+		e => panic!("unexpected error: {}", e),
+	}
+}
+
+#[test]
+fn read_ipv4_skip_invalid() {
+	use input::{InvalidLinePolicy,SetReader};
+
+	let mut reader = SetReader::<Ipv4Cidr>::new(InvalidLinePolicy::Skip);
+	reader.read("a", "foo\n10.0.0.0/8\n".as_bytes()).unwrap();
+	reader.read("b", "192.168.0.0/16\n10.0.0.0/33\n".as_bytes()).unwrap();
+
+	assert_eq!(
+		reader.skipped().iter().map(|e| (e.source.as_str(), e.line)).collect::<Vec<_>>(),
+		vec![("a", 1), ("b", 2)]
+	);
+	assert_eq!(
+		format_lines(reader.into_set().iter().collect::<Vec<_>>()),
+		concat!(
+			"10.0.0.0/8\n",
+			"192.168.0.0/16\n",
+		)
+	);
+}
+
+#[test]
+fn read_missing_file() {
+	use input::{InvalidLinePolicy,ReadError,SetReader};
+
+	let mut reader = SetReader::<Ipv4Cidr>::new(InvalidLinePolicy::Strict);
+	match reader.read_file("/nonexistent/compress-cidr-input") {
+		Err(ReadError::Open{source, ..}) => assert_eq!(source, "/nonexistent/compress-cidr-input"),
+		r => panic!("unexpected result: {:?}", r),
+	}
+}
+
+#[test]
+fn read_progress() {
+	use input::{InvalidLinePolicy,ReadError,ReadProgress,SetReader};
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	let reports = Rc::new(RefCell::new(Vec::new()));
+	let mut reader = SetReader::<Ipv4Cidr>::new(InvalidLinePolicy::Strict);
+	{
+		let reports = reports.clone();
+		reader.set_progress(2, move |progress| reports.borrow_mut().push(*progress));
+	}
+	// CRLF line endings, missing newline at the end
+	reader.read("a", "10.0.0.0/8\r\n# comment\r\n192.168.0.0/16".as_bytes()).unwrap();
+	reader.read("b", "172.16.0.0/12\n".as_bytes()).unwrap();
+	assert_eq!(*reports.borrow(), vec![
+		ReadProgress{ lines: 2, bytes: 23 },
+		ReadProgress{ lines: 4, bytes: 51 },
+	]);
+	assert_eq!(reader.progress(), ReadProgress{ lines: 4, bytes: 51 });
+	assert_eq!(
+		set_lines(reader.set()),
+		concat!(
+			"10.0.0.0/8\n",
+			"172.16.0.0/12\n",
+			"192.168.0.0/16\n",
+		)
+	);
+
+	match reader.read("c", &b"10.0.0.0/8\n 1.2.3.\xff\n"[..]) {
+		Err(ReadError::Parse(e)) => assert_eq!(
+			e.to_string(),
+			"c:2:2: invalid UTF-8 after 7 bytes: \"1.2.3.\u{fffd}\""
+		),
+		r => panic!("unexpected result: {:?}", r),
+	}
+
+	// invalid UTF-8 is just another invalid line
+	let mut reader = SetReader::<Ipv4Cidr>::new(InvalidLinePolicy::Skip);
+	reader.read("d", &b"\xff\n10.0.0.0/8\n"[..]).unwrap();
+	assert_eq!(reader.skipped().len(), 1);
+	assert_eq!(set_lines(reader.set()), "10.0.0.0/8\n");
+}
+
+#[test]
+fn read_ipv4_fast_path() {
+	use cidr::{AnyIpCidr,Ipv6Cidr};
+	use input::{EmbeddedIpv4Policy,HostBitsPolicy,InvalidLinePolicy,ReadOptions,SetReader};
+
+	// lines the byte parser must leave to the generic parsers (some of
+	// them are still valid)
+	let input = concat!(
+		"010.0.0.0/8\n",
+		"10.0.0.256\n",
+		"10.0.0/24\n",
+		"10.0.0.0.0\n",
+		"10.0.0.0/33\n",
+		"10.0.0.0/024\n",
+		"10.1.2.3/8\n",
+		"10.0.0.0/+8\n",
+	);
+	let mut reader = SetReader::<Ipv4Cidr>::new(InvalidLinePolicy::Skip);
+	reader.read("a", input.as_bytes()).unwrap();
+	assert_eq!(
+		reader.skipped().iter().map(|e| e.line).collect::<Vec<_>>(),
+		vec![2, 4, 5, 7]
+	);
+	assert_eq!(set_lines(reader.set()), "10.0.0.0/8\n");
+
+	let mut reader = SetReader::<Ipv4Cidr>::with_options(ReadOptions{
+		invalid_lines: InvalidLinePolicy::Strict,
+		host_bits: HostBitsPolicy::Truncate,
+		..Default::default()
+	});
+	reader.read("b", " 10.1.2.3/8 \n192.168.0.1\n0.0.0.0/0\n".as_bytes()).unwrap();
+	assert_eq!(reader.host_bits_adjusted(), 1);
+	assert_eq!(set_lines(reader.set()), "0.0.0.0/0\n");
+
+	// IPv4 lines are still normalised or rejected for other families
+	let mut reader = SetReader::<Ipv6Cidr>::with_options(ReadOptions{
+		embedded_ipv4: EmbeddedIpv4Policy::Lift,
+		..Default::default()
+	});
+	reader.read("c", "192.0.2.0/24\n".as_bytes()).unwrap();
+	assert_eq!(set_lines(reader.set()), "::ffff:192.0.2.0/120\n");
+	let mut reader = SetReader::<Ipv6Cidr>::new(InvalidLinePolicy::Skip);
+	reader.read("d", "192.0.2.0/24\n".as_bytes()).unwrap();
+	assert_eq!(reader.skipped().len(), 1);
+	let mut reader = SetReader::<AnyIpCidr>::new(InvalidLinePolicy::Strict);
+	reader.read("e", "192.0.2.0/24\n".as_bytes()).unwrap();
+	assert_eq!(set_lines(reader.set()), "192.0.2.0/24\n");
+}
+
+// tests for set operations
+
+fn ipv4_set(prefixes: &[&str]) -> RadixSet<Ipv4Cidr> {
+	let mut rs : RadixSet<Ipv4Cidr> = Default::default();
+	for p in prefixes {
+		rs.insert(Ipv4Cidr::from_str(p).unwrap());
+	}
+	rs
+}
+
+fn set_lines<S: ::bitstring::BitString+Clone+fmt::Display>(rs: &RadixSet<S>) -> String {
+	format_lines(rs.iter().collect::<Vec<_>>())
+}
+
+#[test]
+fn set_ops_ipv4() {
+	use set_ops::*;
+
+	let a = ipv4_set(&["10.0.0.0/8", "192.168.0.0/16"]);
+	let b = ipv4_set(&["10.128.0.0/9", "172.16.0.0/12"]);
+
+	assert_eq!(
+		set_lines(&union(&a, &b)),
+		concat!(
+			"10.0.0.0/8\n",
+			"172.16.0.0/12\n",
+			"192.168.0.0/16\n",
+		)
+	);
+
+	assert_eq!(
+		set_lines(&intersection(&a, &b)),
+		concat!(
+			"10.128.0.0/9\n",
+		)
+	);
+
+	assert_eq!(
+		set_lines(&difference(&a, &b)),
+		concat!(
+			"10.0.0.0/9\n",
+			"192.168.0.0/16\n",
+		)
+	);
+
+	assert_eq!(
+		set_lines(&symmetric_difference(&a, &b)),
+		concat!(
+			"10.0.0.0/9\n",
+			"172.16.0.0/12\n",
+			"192.168.0.0/16\n",
+		)
+	);
+}
+
+#[test]
+fn set_ops_ipv4_empty() {
+	use set_ops::*;
+
+	let a = ipv4_set(&["10.0.0.0/8"]);
+	let empty = ipv4_set(&[]);
+	let any = ipv4_set(&["0.0.0.0/0"]);
+
+	assert_eq!(set_lines(&union(&empty, &a)), "10.0.0.0/8\n");
+	assert_eq!(set_lines(&intersection(&empty, &a)), "");
+	assert_eq!(set_lines(&intersection(&any, &a)), "10.0.0.0/8\n");
+	assert_eq!(set_lines(&difference(&a, &any)), "");
+	assert_eq!(
+		set_lines(&difference(&any, &a)),
+		set_lines(&ipv4_set(&[
+			"0.0.0.0/5", "8.0.0.0/7", "11.0.0.0/8", "12.0.0.0/6",
+			"16.0.0.0/4", "32.0.0.0/3", "64.0.0.0/2", "128.0.0.0/1",
+		]))
+	);
+}
+
+#[test]
+fn set_ops_fold() {
+	use set_ops::SetOperation;
+
+	let sets = vec![
+		ipv4_set(&["10.0.0.0/8"]),
+		ipv4_set(&["10.0.0.0/9"]),
+		ipv4_set(&["10.192.0.0/10"]),
+	];
+
+	assert_eq!(
+		set_lines(&SetOperation::Difference.fold(sets)),
+		"10.128.0.0/10\n"
+	);
+	assert_eq!(set_lines(&SetOperation::Union.fold(Vec::<RadixSet<Ipv4Cidr>>::new())), "");
+}
+
+// tests for lookup
+
+#[test]
+fn lookup_ipv4_compressed() {
+	use set_def::Lookup;
+
+	let rs = ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11"]);
+	let defs = Definition::compress(&rs, false);
+	let q = |s: &str| Definition::lookup(&defs, &Ipv4Cidr::from_str(s).unwrap());
+
+	assert_eq!(
+		format_lines(&defs),
+		concat!(
+			"include 10.0.0.0/8\n",
+			"exclude 10.192.0.0/11\n",
+		)
+	);
+
+	assert_eq!(q("10.1.2.3"), Lookup::Match(&defs[0]));
+	assert_eq!(q("10.192.0.1"), Lookup::Match(&defs[1]));
+	assert_eq!(q("11.0.0.0/8"), Lookup::Default);
+	assert_eq!(q("10.0.0.0/8"), Lookup::Partial(Some(&defs[0])));
+	assert_eq!(q("0.0.0.0/0"), Lookup::Partial(None));
+	assert_eq!(q("10.192.0.0/11").included(), Some(false));
+	assert_eq!(q("10.0.0.0/8").included(), None);
+}
+
+#[test]
+fn lookup_ipv4_redundant() {
+	use set_def::Lookup;
+
+	// the exclude is completely overridden again
+	let defs = ipv4_defs(&[
+		"include 10.0.0.0/8",
+		"exclude 10.0.0.0/16",
+		"include 10.0.0.0/17",
+		"include 10.0.128.0/17",
+		"exclude 10.1.0.0/16",
+		"exclude 10.1.0.0/17",
+		"include 10.2.0.0/16",
+		"exclude 10.2.0.0/17",
+		"exclude 10.2.128.0/17",
+	]);
+	let q = |s: &str| Definition::lookup(&defs, &Ipv4Cidr::from_str(s).unwrap());
+
+	assert_eq!(q("10.0.0.0/16"), Lookup::Covered(true));
+	assert_eq!(q("10.0.0.0/16").included(), Some(true));
+	assert_eq!(q("10.0.0.0/15"), Lookup::Partial(Some(&defs[0])));
+	assert_eq!(q("10.1.0.0/16"), Lookup::Match(&defs[4]));
+	// everything below the include is excluded again
+	assert_eq!(q("10.2.0.0/16"), Lookup::Covered(false));
+	assert_eq!(q("10.2.0.0/15"), Lookup::Partial(Some(&defs[0])));
+	assert_eq!(q("10.3.0.0/16"), Lookup::Match(&defs[0]));
+
+	let defs = ipv4_defs(&[
+		"include 10.0.0.0/8",
+		"exclude 10.0.0.0/16",
+		"include 10.0.0.0/17",
+		"include 10.0.128.0/17",
+		"include 10.1.0.0/16",
+	]);
+	assert_eq!(Definition::lookup(&defs, &Ipv4Cidr::from_str("10.0.0.0/8").unwrap()), Lookup::Match(&defs[0]));
+	assert_eq!(Definition::lookup(&defs, &Ipv4Cidr::from_str("0.0.0.0/0").unwrap()), Lookup::Partial(None));
+	let defs = ipv4_defs(&["exclude 10.0.0.0/8", "exclude 10.1.0.0/16"]);
+	assert_eq!(Definition::lookup(&defs, &Ipv4Cidr::from_str("0.0.0.0/0").unwrap()), Lookup::Default);
+}
+
+#[test]
+fn lookup_ipv4_set() {
+	use set_ops::contains;
+
+	let rs = ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11"]);
+	let q = |s: &str| contains(&rs, &Ipv4Cidr::from_str(s).unwrap());
+
+	assert_eq!(q("10.1.2.3"), Some(true));
+	assert_eq!(q("10.192.0.1"), Some(false));
+	assert_eq!(q("11.0.0.0/8"), Some(false));
+	assert_eq!(q("10.0.0.0/8"), None);
+	assert_eq!(q("10.224.0.0/12"), Some(true));
+	assert_eq!(q("0.0.0.0/0"), None);
+	assert_eq!(contains(&ipv4_set(&[]), &Ipv4Cidr::from_str("0.0.0.0/0").unwrap()), Some(false));
+}
+
+// tests for definition lists
+
+fn ipv4_defs(lines: &[&str]) -> Vec<Definition<Ipv4Cidr>> {
+	lines.iter().map(|l| l.parse().unwrap()).collect()
+}
+
+#[test]
+fn parse_definition() {
+	use set_def::ParseDefinitionError;
+
+	assert_eq!(
+		Definition::<Ipv4Cidr>::from_str("include 10.0.0.0/8"),
+		Ok(Definition{ prefix: Ipv4Cidr::from_str("10.0.0.0/8").unwrap(), label: true })
+	);
+	assert_eq!(
+		Definition::<Ipv4Cidr>::from_str("exclude  10.0.0.0/8"),
+		Ok(Definition{ prefix: Ipv4Cidr::from_str("10.0.0.0/8").unwrap(), label: false })
+	);
+	assert_eq!(
+		Definition::<Ipv4Cidr>::from_str("10.0.0.0/8"),
+		Err(ParseDefinitionError::InvalidSyntax)
+	);
+	assert_eq!(
+		Definition::<Ipv4Cidr>::from_str("allow 10.0.0.0/8"),
+		Err(ParseDefinitionError::UnknownKeyword("allow".to_string()))
+	);
+	assert!(Definition::<Ipv4Cidr>::from_str("include 10.0.0.1/8").is_err());
+}
+
+#[test]
+fn evaluate_roundtrip() {
+	let rs = ipv4_set(&["0.0.0.0/32", "10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11", "192.168.0.0/16"]);
+
+	for invert in [false, true].iter() {
+		let compressed = Definition::compress(&rs, *invert);
+		let complete = Definition::complete(&rs, *invert);
+		assert_eq!(
+			set_lines(&Definition::evaluate(&compressed)),
+			set_lines(&Definition::evaluate(&complete))
+		);
+	}
+	assert_eq!(set_lines(&Definition::evaluate(&Definition::compress(&rs, false))), set_lines(&rs));
+}
+
+#[test]
+fn evaluate_redundant() {
+	let defs = ipv4_defs(&[
+		"exclude 10.1.0.0/16",
+		"include 10.0.0.0/8",
+		"include 10.1.0.0/17",
+		"exclude 10.1.128.0/17",
+		"exclude 10.0.0.0/8", // duplicate; first one wins
+		"exclude 11.0.0.0/8",
+	]);
+
+	assert_eq!(
+		set_lines(&Definition::evaluate(&defs)),
+		concat!(
+			"10.0.0.0/16\n",
+			"10.1.0.0/17\n",
+			"10.2.0.0/15\n",
+			"10.4.0.0/14\n",
+			"10.8.0.0/13\n",
+			"10.16.0.0/12\n",
+			"10.32.0.0/11\n",
+			"10.64.0.0/10\n",
+			"10.128.0.0/9\n",
+		)
+	);
+	assert_eq!(
+		format_lines(Definition::compress(&Definition::evaluate(&defs), false)),
+		concat!(
+			"include 10.0.0.0/8\n",
+			"exclude 10.1.128.0/17\n",
+		)
+	);
+}
+
+#[test]
+fn read_definitions() {
+	use input::{InputFormat,ReadOptions,SetReader};
+
+	let mut reader = SetReader::<Ipv4Cidr>::with_options(ReadOptions{
+		format: InputFormat::Definitions,
+		..Default::default()
+	});
+	reader.read("a", "include 10.0.0.0/8\nexclude 10.128.0.0/9\n".as_bytes()).unwrap();
+	reader.read("b", "# comment\nexclude 10.0.0.0/8\ninclude 192.168.0.0/16\n".as_bytes()).unwrap();
+
+	assert_eq!(
+		set_lines(&reader.into_set()),
+		concat!(
+			"10.0.0.0/9\n",
+			"192.168.0.0/16\n",
+		)
+	);
+}
+
+#[test]
+fn read_definition_list_skip() {
+	use input::{read_definitions,read_definitions_with,InvalidLinePolicy,ReadError};
+
+	let input = &b"include 10.0.0.0/8\nbogus\n\xff\nexclude 10.1.0.0/16\n"[..];
+	match read_definitions::<Ipv4Cidr, _>("a", input) {
+		Err(ReadError::Parse(e)) => assert_eq!(e.line, 2),
+		r => panic!("unexpected result: {:?}", r),
+	}
+
+	let mut skipped = Vec::new();
+	let defs = read_definitions_with::<Ipv4Cidr, _>("a", input, InvalidLinePolicy::Skip, &mut skipped).unwrap();
+	assert_eq!(defs, ipv4_defs(&["include 10.0.0.0/8", "exclude 10.1.0.0/16"]));
+	assert_eq!(skipped.iter().map(|e| e.line).collect::<Vec<_>>(), vec![2, 3]);
+}
+
+// tests for verification
+
+#[test]
+fn verify_ipv4() {
+	let sets = vec![
+		ipv4_set(&[]),
+		ipv4_set(&["0.0.0.0/0"]),
+		ipv4_set(&["0.0.0.0/32"]),
+		ipv4_set(&["255.255.255.255/32", "10.0.0.0/8"]),
+		ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11", "192.168.0.0/16"]),
+	];
+
+	for rs in &sets {
+		for invert in [false, true].iter() {
+			assert_eq!(Definition::verify(&Definition::compress(rs, *invert), rs, *invert), Ok(()));
+			assert_eq!(Definition::verify(&Definition::complete(rs, *invert), rs, *invert), Ok(()));
+		}
+	}
+}
+
+#[test]
+fn verify_ipv4_mismatch() {
+	use set_def::Mismatch;
+
+	let rs = ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11"]);
+	let defs = ipv4_defs(&["include 10.0.0.0/8", "exclude 10.192.0.0/10"]);
+
+	assert_eq!(
+		Definition::verify(&defs, &rs, false),
+		Err(Mismatch{ prefix: Ipv4Cidr::from_str("10.224.0.0/11").unwrap(), included: false })
+	);
+	assert_eq!(
+		Definition::verify(&defs, &rs, true),
+		Err(Mismatch{ prefix: Ipv4Cidr::from_str("0.0.0.0/5").unwrap(), included: false })
+	);
+	assert_eq!(
+		Definition::verify(&ipv4_defs(&["include 10.0.0.0/8"]), &rs, false),
+		Err(Mismatch{ prefix: Ipv4Cidr::from_str("10.192.0.0/11").unwrap(), included: true })
+	);
+}
+
+// tests for address ranges
+
+fn ipv4_cover(first: &str, last: &str) -> String {
+	format_lines(::range::cover(
+		&Ipv4Cidr::from_str(first).unwrap(),
+		&Ipv4Cidr::from_str(last).unwrap(),
+	))
+}
+
+#[test]
+fn range_cover_ipv4() {
+	assert_eq!(ipv4_cover("10.0.0.5", "10.0.0.5"), "10.0.0.5/32\n");
+	assert_eq!(ipv4_cover("0.0.0.0", "255.255.255.255"), "0.0.0.0/0\n");
+	assert_eq!(ipv4_cover("10.0.0.0", "10.0.255.255"), "10.0.0.0/16\n");
+	assert_eq!(ipv4_cover("10.0.0.255", "10.0.1.0"), "10.0.0.255/32\n10.0.1.0/32\n");
+	assert_eq!(
+		ipv4_cover("10.0.0.5", "10.0.3.17"),
+		concat!(
+			"10.0.0.5/32\n",
+			"10.0.0.6/31\n",
+			"10.0.0.8/29\n",
+			"10.0.0.16/28\n",
+			"10.0.0.32/27\n",
+			"10.0.0.64/26\n",
+			"10.0.0.128/25\n",
+			"10.0.1.0/24\n",
+			"10.0.2.0/24\n",
+			"10.0.3.0/28\n",
+			"10.0.3.16/31\n",
+		)
+	);
+}
+
+#[test]
+fn range_parse() {
+	use cidr::{AnyIpCidr,Ipv6Cidr};
+	use range::{parse_range,ParseRangeError};
+
+	assert_eq!(
+		format_lines(parse_range::<Ipv6Cidr>("2001:db8::1 - 2001:db8::ffff").unwrap()),
+		concat!(
+			"2001:db8::1/128\n",
+			"2001:db8::2/127\n",
+			"2001:db8::4/126\n",
+			"2001:db8::8/125\n",
+			"2001:db8::10/124\n",
+			"2001:db8::20/123\n",
+			"2001:db8::40/122\n",
+			"2001:db8::80/121\n",
+			"2001:db8::100/120\n",
+			"2001:db8::200/119\n",
+			"2001:db8::400/118\n",
+			"2001:db8::800/117\n",
+			"2001:db8::1000/116\n",
+			"2001:db8::2000/115\n",
+			"2001:db8::4000/114\n",
+			"2001:db8::8000/113\n",
+		)
+	);
+	assert_eq!(
+		format_lines(parse_range::<AnyIpCidr>("192.168.0.0-192.168.1.255").unwrap()),
+		"192.168.0.0/23\n"
+	);
+	assert_eq!(parse_range::<AnyIpCidr>("10.0.0.0-::1"), Err(ParseRangeError::FamilyMismatch));
+	assert_eq!(parse_range::<Ipv4Cidr>("10.0.0.2-10.0.0.1"), Err(ParseRangeError::Reversed));
+	assert_eq!(parse_range::<Ipv4Cidr>("10.0.0.0/8-10.0.0.1"), Err(ParseRangeError::NotAnAddress));
+	assert_eq!(parse_range::<Ipv4Cidr>("10.0.0.1-10.0.0.2-10.0.0.3"), Err(ParseRangeError::InvalidSyntax));
+}
+
+#[test]
+fn read_ranges() {
+	let input = "10.0.0.5-10.0.0.7\n10.0.0.4\n10.0.1.0 - 10.0.1.255\n";
+	let rs : RadixSet<Ipv4Cidr> = ::input::read(input.as_bytes()).unwrap();
+
+	assert_eq!(
+		set_lines(&rs),
+		concat!(
+			"10.0.0.4/30\n",
+			"10.0.1.0/24\n",
+		)
+	);
+}
+
+// tests for host bits policy
+
+#[test]
+fn read_host_bits() {
+	use cidr::AnyIpCidr;
+	use input::{HostBitsPolicy,InvalidLinePolicy,ReadOptions,SetReader};
+
+	let input = "192.168.1.7/24\n10.0.0.0/8\n2001:db8::1/32\n";
+	let read = |host_bits| {
+		let mut reader = SetReader::<AnyIpCidr>::with_options(ReadOptions{
+			invalid_lines: InvalidLinePolicy::Skip,
+			host_bits,
+			..Default::default()
+		});
+		reader.read("input", input.as_bytes()).unwrap();
+		(reader.skipped().len(), reader.host_bits_adjusted(), set_lines(reader.set()))
+	};
+
+	assert_eq!(read(HostBitsPolicy::Reject), (2, 0, "10.0.0.0/8\n".to_string()));
+	assert_eq!(
+		read(HostBitsPolicy::Truncate),
+		(0, 2, "10.0.0.0/8\n192.168.1.0/24\n2001:db8::/32\n".to_string())
+	);
+	assert_eq!(
+		read(HostBitsPolicy::Host),
+		(0, 2, "10.0.0.0/8\n192.168.1.7/32\n2001:db8::1/128\n".to_string())
+	);
+}
+
+#[test]
+fn parse_entry_host_bits() {
+	use input::{parse_entry,Entry,HostBitsPolicy};
+
+	let p = |s: &str| Ipv4Cidr::from_str(s).unwrap();
+
+	assert_eq!(parse_entry::<Ipv4Cidr>("10.1.2.3/8", HostBitsPolicy::Truncate), Ok(Entry::HostBits(p("10.0.0.0/8"))));
+	assert_eq!(parse_entry::<Ipv4Cidr>("10.0.0.0/8", HostBitsPolicy::Truncate), Ok(Entry::Prefix(p("10.0.0.0/8"))));
+	assert!(parse_entry::<Ipv4Cidr>("10.1.2.3/8", HostBitsPolicy::Reject).is_err());
+	assert!(parse_entry::<Ipv4Cidr>("10.1.2.3/33", HostBitsPolicy::Truncate).is_err());
+	assert!(parse_entry::<Ipv4Cidr>("10.1.2.0/24/8", HostBitsPolicy::Host).is_err());
+}
+
+#[test]
+fn read_embedded_ipv4() {
+	use cidr::AnyIpCidr;
+	use input::{EmbeddedIpv4Policy,InvalidLinePolicy,ReadOptions,SetReader};
+
+	let input = "::ffff:192.0.2.1\n10.0.0.0/8\n::ffff:10.1.0.0/112\n2002:c633:6400::/40\n64:ff9b::c633:6407\n::203.0.113.0/120\n";
+	let read = |embedded_ipv4| {
+		let mut reader = SetReader::<AnyIpCidr>::with_options(ReadOptions{
+			embedded_ipv4,
+			..Default::default()
+		});
+		reader.read("input", input.as_bytes()).unwrap();
+		set_lines(reader.set())
+	};
+
+	assert_eq!(
+		read(EmbeddedIpv4Policy::Fold{six_to_four: false, nat64: false, compatible: false}),
+		"10.0.0.0/8\n192.0.2.1/32\n::cb00:7100/120\n64:ff9b::c633:6407/128\n2002:c633:6400::/40\n"
+	);
+	assert_eq!(
+		read(EmbeddedIpv4Policy::Fold{six_to_four: true, nat64: true, compatible: true}),
+		"10.0.0.0/8\n192.0.2.1/32\n198.51.100.0/24\n203.0.113.0/24\n"
+	);
+	assert_eq!(
+		read(EmbeddedIpv4Policy::Lift),
+		"::cb00:7100/120\n::ffff:10.0.0.0/104\n::ffff:192.0.2.1/128\n64:ff9b::c633:6407/128\n2002:c633:6400::/40\n"
+	);
+
+	// other IPv6 ranges are still invalid in IPv4 mode
+	let mut reader = SetReader::<Ipv4Cidr>::with_options(ReadOptions{
+		invalid_lines: InvalidLinePolicy::Skip,
+		embedded_ipv4: EmbeddedIpv4Policy::Fold{six_to_four: false, nat64: true, compatible: false},
+		..Default::default()
+	});
+	reader.read("input", input.as_bytes()).unwrap();
+	assert_eq!(reader.skipped().len(), 2);
+	assert_eq!(set_lines(reader.set()), "10.0.0.0/8\n192.0.2.1/32\n198.51.100.7/32\n");
+}
+
+#[test]
+fn embedding_fold_lift() {
+	use cidr::Ipv6Cidr;
+	use family::Ipv4Embedding;
+
+	let v6 = |s: &str| Ipv6Cidr::from_str(s).unwrap();
+	let v4 = |s: &str| Ipv4Cidr::from_str(s).unwrap();
+
+	assert_eq!(Ipv4Embedding::Mapped.fold(&v6("::ffff:10.0.0.0/104")), Some(v4("10.0.0.0/8")));
+	assert_eq!(Ipv4Embedding::Mapped.fold(&v6("::fffe:0:0/95")), None);
+	assert_eq!(Ipv4Embedding::Mapped.fold(&v6("::fffe:0:0/96")), None);
+	assert_eq!(Ipv4Embedding::SixToFour.fold(&v6("2002:c000:0200::/40")), Some(v4("192.0.2.0/24")));
+	assert_eq!(Ipv4Embedding::SixToFour.fold(&v6("2002:c000:0201:1::/64")), None);
+	assert_eq!(Ipv4Embedding::Nat64.fold(&v6("64:ff9b::/96")), Some(v4("0.0.0.0/0")));
+	assert_eq!(Ipv4Embedding::Nat64.lift(&v4("192.0.2.1/32")), v6("64:ff9b::c000:201/128"));
+	assert_eq!(Ipv4Embedding::SixToFour.lift(&v4("192.0.2.0/24")), v6("2002:c000:0200::/40"));
+	assert_eq!(Ipv4Embedding::Compatible.fold(&v6("::192.0.2.0/120")), Some(v4("192.0.2.0/24")));
+	assert_eq!(Ipv4Embedding::Compatible.fold(&v6("::1/128")), Some(v4("0.0.0.1/32")));
+	assert_eq!(Ipv4Embedding::Compatible.fold(&v6("::ffff:0:0/96")), None);
+	assert_eq!(Ipv4Embedding::Compatible.fold(&v6("::/95")), None);
+	assert_eq!(Ipv4Embedding::Compatible.lift(&v4("192.0.2.1/32")), v6("::c000:201/128"));
+}
+
+// tests for lossy compression
+
+#[test]
+fn compress_lossy_ipv4() {
+	let rs = ipv4_set(&["10.0.0.0/24", "10.0.2.0/24", "10.0.4.0/23", "192.168.0.0/16"]);
+
+	let exact = Definition::compress(&rs, false);
+	let lossy = Definition::compress_lossy(&rs, false, 10).unwrap();
+	assert_eq!(lossy.over_covered, 0);
+	assert_eq!(Definition::verify(&lossy.definitions, &rs, false), Ok(()));
+	assert!(lossy.definitions.len() <= exact.len());
+
+	let lossy = Definition::compress_lossy(&rs, false, 3).unwrap();
+	assert_eq!(
+		format_lines(&lossy.definitions),
+		concat!(
+			"include 10.0.0.0/22\n",
+			"include 10.0.4.0/23\n",
+			"include 192.168.0.0/16\n",
+		)
+	);
+	assert_eq!(lossy.over_covered, 2 * 256);
+
+	let lossy = Definition::compress_lossy(&rs, false, 2).unwrap();
+	assert_eq!(
+		format_lines(&lossy.definitions),
+		concat!(
+			"include 10.0.0.0/21\n",
+			"include 192.168.0.0/16\n",
+		)
+	);
+	assert_eq!(lossy.over_covered, 4 * 256);
+
+	let lossy = Definition::compress_lossy(&rs, false, 1).unwrap();
+	assert_eq!(format_lines(&lossy.definitions), "include 0.0.0.0/0\n");
+	assert_eq!(lossy.over_covered, (1u128 << 32) - 65536 - 4 * 256);
+
+	assert_eq!(Definition::compress_lossy(&rs, false, 0), None);
+}
+
+#[test]
+fn compress_lossy_ipv4_empty() {
+	let rs = ipv4_set(&[]);
+
+	let lossy = Definition::compress_lossy(&rs, false, 0).unwrap();
+	assert!(lossy.definitions.is_empty());
+	assert_eq!(lossy.over_covered, 0);
+
+	let lossy = Definition::compress_lossy(&rs, true, 1).unwrap();
+	assert_eq!(format_lines(&lossy.definitions), "include 0.0.0.0/0\n");
+	assert_eq!(lossy.over_covered, 0);
+}
+
+#[test]
+fn compress_lossy_ipv4_inverted() {
+	let rs = ipv4_set(&["0.0.0.0/32", "128.0.0.0/2", "255.255.255.255/32"]);
+
+	let lossy = Definition::compress_lossy(&rs, true, 2).unwrap();
+	assert_eq!(
+		format_lines(&lossy.definitions),
+		concat!(
+			"include 0.0.0.0/1\n",
+			"include 192.0.0.0/2\n",
+		)
+	);
+	assert_eq!(lossy.over_covered, 2);
+}
+
+// tests for don't care ranges
+
+#[test]
+fn compress_dont_care_ipv4() {
+	let rs = ipv4_set(&["10.0.0.0/24", "10.0.2.0/24", "10.0.3.0/24"]);
+
+	let defs = Definition::compress_dont_care(&rs, &ipv4_set(&[]), false);
+	assert_eq!(defs.len(), Definition::compress(&rs, false).len());
+	assert_eq!(Definition::verify(&defs, &rs, false), Ok(()));
+	let dc = ipv4_set(&["10.0.1.0/24"]);
+	assert_eq!(
+		format_lines(Definition::compress_dont_care(&rs, &dc, false)),
+		concat!(
+			"include 10.0.0.0/22\n",
+		)
+	);
+	assert_eq!(
+		format_lines(Definition::compress_dont_care(&rs, &dc, true)),
+		concat!(
+			"include 0.0.0.0/0\n",
+			"exclude 10.0.0.0/22\n",
+		)
+	);
+
+	let rs = ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11"]);
+	let dc = ipv4_set(&["10.192.0.0/11", "192.168.0.0/16"]);
+	assert_eq!(
+		format_lines(Definition::compress_dont_care(&rs, &dc, false)),
+		concat!(
+			"include 10.0.0.0/8\n",
+		)
+	);
+}
+
+#[test]
+fn compress_dont_care_ipv4_overlap() {
+	// don't care wins over the set
+	let rs = ipv4_set(&["10.0.0.0/8", "192.168.0.0/16"]);
+	let dc = ipv4_set(&["10.0.0.0/8", "192.168.0.0/17"]);
+	let defs = Definition::compress_dont_care(&rs, &dc, false);
+	assert_eq!(
+		format_lines(&defs),
+		concat!(
+			"include 192.168.0.0/16\n",
+		)
+	);
+
+	let dc = ipv4_set(&["0.0.0.0/0"]);
+	assert!(Definition::compress_dont_care(&rs, &dc, false).is_empty());
+	assert!(Definition::compress_dont_care(&rs, &dc, true).is_empty());
+}
+
+#[test]
+fn verify_dont_care_ipv4() {
+	use set_def::Mismatch;
+
+	let rs = ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11"]);
+	let dc = ipv4_set(&["10.192.0.0/11"]);
+	let defs = ipv4_defs(&["include 10.0.0.0/8"]);
+
+	assert_eq!(Definition::verify_dont_care(&defs, &rs, &dc, false), Ok(()));
+	assert_eq!(
+		Definition::verify_dont_care(&defs, &rs, &ipv4_set(&["10.192.0.0/12"]), false),
+		Err(Mismatch{ prefix: Ipv4Cidr::from_str("10.208.0.0/12").unwrap(), included: true })
+	);
+	for invert in [false, true].iter() {
+		let defs = Definition::compress_dont_care(&rs, &dc, *invert);
+		assert_eq!(Definition::verify_dont_care(&defs, &rs, &dc, *invert), Ok(()));
+	}
+}
+
+// tests for labels
+
+fn ipv4_map(entries: &[(&str, &'static str)]) -> ::bitstring_trees::map::RadixMap<Ipv4Cidr, &'static str> {
+	let mut map = ::bitstring_trees::map::RadixMap::default();
+	for &(prefix, label) in entries {
+		map.insert(Ipv4Cidr::from_str(prefix).unwrap(), label);
+	}
+	map
+}
+
+fn label_lines<L: fmt::Display>(defs: &[Definition<Ipv4Cidr, L>]) -> String {
+	defs.iter().map(|d| format!("{} {}\n", d.prefix, d.label)).collect()
+}
+
+#[test]
+fn compress_labels_ipv4() {
+	let map = ipv4_map(&[]);
+	assert_eq!(label_lines(&Definition::compress_labels(&map, &"-")), "");
+	assert_eq!(label_lines(&Definition::compress_labels(&map, &"a")), "");
+
+	let map = ipv4_map(&[
+		("10.0.0.0/10", "a"),
+		("10.64.0.0/10", "a"),
+		("10.128.0.0/10", "b"),
+		("10.192.0.0/10", "a"),
+		("192.168.0.0/16", "-"),
+	]);
+	assert_eq!(
+		label_lines(&Definition::compress_labels(&map, &"-")),
+		concat!(
+			"10.0.0.0/8 a\n",
+			"10.128.0.0/10 b\n",
+		)
+	);
+	assert_eq!(
+		label_lines(&Definition::compress_labels(&map, &"a")),
+		concat!(
+			"10.128.0.0/10 b\n",
+			"192.168.0.0/16 -\n",
+		)
+	);
+}
+
+#[test]
+fn compress_labels_ipv4_ortc() {
+	// all labels below 10.0.0.0/8 are different; one of them should
+	// be used for 10.0.0.0/8, the others need more specific rules
+	let map = ipv4_map(&[
+		("0.0.0.0/0", "x"),
+		("10.0.0.0/9", "a"),
+		("10.128.0.0/10", "b"),
+		("10.192.0.0/10", "x"),
+	]);
+	assert_eq!(
+		label_lines(&Definition::compress_labels(&map, &"-")),
+		concat!(
+			"0.0.0.0/0 x\n",
+			"10.0.0.0/9 a\n",
+			"10.128.0.0/10 b\n",
+		)
+	);
+
+	let map = ipv4_map(&[
+		("10.0.0.0/9", "a"),
+		("10.128.0.0/10", "b"),
+		("10.192.0.0/10", "c"),
+	]);
+	assert_eq!(
+		label_lines(&Definition::compress_labels(&map, &"-")),
+		concat!(
+			"10.0.0.0/8 a\n",
+			"10.128.0.0/9 b\n",
+			"10.192.0.0/10 c\n",
+		)
+	);
+}
+
+#[test]
+fn compress_labels_ipv4_bool() {
+	let rs = ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11", "192.168.0.0/16"]);
+	let mut map = ::bitstring_trees::map::RadixMap::default();
+	for prefix in rs.iter() {
+		map.insert(prefix.clone(), true);
+	}
+	let defs = Definition::compress_labels(&map, &false);
+	assert_eq!(Definition::verify(&defs, &rs, false), Ok(()));
+	assert_eq!(defs.len(), Definition::compress(&rs, false).len());
+}
+
+// tests for optimal compression
+
+#[test]
+fn compress_optimal_ipv4() {
+	let rs = ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11", "192.168.0.0/16"]);
+
+	assert_eq!(
+		format_lines(Definition::compress_optimal(&rs, false)),
+		concat!(
+			"include 10.0.0.0/8\n",
+			"exclude 10.192.0.0/11\n",
+			"include 192.168.0.0/16\n",
+		)
+	);
+	assert_eq!(Definition::optimal_len(&rs, false), 3);
+	assert_eq!(
+		format_lines(Definition::compress_optimal(&rs, true)),
+		concat!(
+			"include 0.0.0.0/0\n",
+			"exclude 10.0.0.0/8\n",
+			"include 10.192.0.0/11\n",
+			"exclude 192.168.0.0/16\n",
+		)
+	);
+	assert_eq!(Definition::optimal_len(&rs, true), 4);
+}
+
+#[test]
+fn compress_optimal_ipv4_edge() {
+	let rs = ipv4_set(&[]);
+	assert_eq!(format_lines(Definition::compress_optimal(&rs, false)), "");
+	assert_eq!(format_lines(Definition::compress_optimal(&rs, true)), "include 0.0.0.0/0\n");
+	assert_eq!(Definition::optimal_len(&rs, false), 0);
+	assert_eq!(Definition::optimal_len(&rs, true), 1);
+
+	let rs = ipv4_set(&["0.0.0.0/0"]);
+	assert_eq!(format_lines(Definition::compress_optimal(&rs, false)), "include 0.0.0.0/0\n");
+	assert_eq!(format_lines(Definition::compress_optimal(&rs, true)), "");
+
+	// excluding the single neighbour of the path is shorter than
+	// excluding the complete region
+	let rs = ipv4_set(&["0.0.0.0/1", "255.255.255.255/32"]);
+	assert_eq!(
+		format_lines(Definition::compress_optimal(&rs, true)),
+		concat!(
+			"include 128.0.0.0/1\n",
+			"exclude 255.255.255.255/32\n",
+		)
+	);
+}
+
+#[test]
+fn compress_optimal_ipv4_matches_heuristic() {
+	let sets = vec![
+		ipv4_set(&["255.255.255.255/32", "10.0.0.0/8"]),
+		ipv4_set(&["10.0.0.0/24", "10.0.2.0/24", "10.0.4.0/23", "192.168.0.0/16"]),
+		ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11", "10.255.255.0/24"]),
+	];
+
+	for rs in &sets {
+		for invert in [false, true].iter() {
+			let defs = Definition::compress_optimal(rs, *invert);
+			assert_eq!(Definition::verify(&defs, rs, *invert), Ok(()));
+			assert_eq!(defs.len(), Definition::optimal_len(rs, *invert));
+			assert_eq!(defs.len(), Definition::compress(rs, *invert).len());
+		}
+	}
+}
+
+// tests for weighted compression
+
+#[test]
+fn compress_weighted_ipv4() {
+	use set_def::RuleCosts;
+
+	let rs = ipv4_set(&["10.0.0.0/9", "10.128.0.0/10", "10.224.0.0/11"]);
+	let unit = RuleCosts::default();
+	assert_eq!(
+		format_lines(Definition::compress_weighted(&rs, false, |p, i| unit.cost(p, i))),
+		concat!(
+			"include 10.0.0.0/8\n",
+			"exclude 10.192.0.0/11\n",
+		)
+	);
+
+	let expensive_exclude = RuleCosts{
+		exclude: 3,
+		..Default::default()
+	};
+	assert_eq!(
+		format_lines(Definition::compress_weighted(&rs, false, |p, i| expensive_exclude.cost(p, i))),
+		concat!(
+			"include 10.0.0.0/9\n",
+			"include 10.128.0.0/10\n",
+			"include 10.224.0.0/11\n",
+		)
+	);
+
+	// only /8 is cheap
+	let mut per_length = vec![10; 33];
+	per_length[8] = 0;
+	let by_length = RuleCosts{
+		per_length,
+		..Default::default()
+	};
+	let defs = Definition::compress_weighted(&rs, true, |p, i| by_length.cost(p, i));
+	assert_eq!(Definition::verify(&defs, &rs, true), Ok(()));
+	assert_eq!(
+		format_lines(defs),
+		concat!(
+			"include 0.0.0.0/0\n",
+			"exclude 10.0.0.0/8\n",
+			"include 10.192.0.0/11\n",
+		)
+	);
+}
+
+#[test]
+fn compress_weighted_ipv4_path() {
+	use set_def::RuleCosts;
+
+	// excludes are expensive: include all neighbours of the path to
+	// 10.1.0.0/16 instead
+	let rs = ipv4_set(&["10.1.0.0/16"]);
+	let costs = RuleCosts{
+		exclude: 20,
+		..Default::default()
+	};
+	let defs = Definition::compress_weighted(&rs, true, |p, i| costs.cost(p, i));
+	assert_eq!(Definition::verify(&defs, &rs, true), Ok(()));
+	assert_eq!(
+		format_lines(defs),
+		concat!(
+			"include 0.0.0.0/5\n",
+			"include 8.0.0.0/7\n",
+			"include 10.0.0.0/16\n",
+			"include 10.2.0.0/15\n",
+			"include 10.4.0.0/14\n",
+			"include 10.8.0.0/13\n",
+			"include 10.16.0.0/12\n",
+			"include 10.32.0.0/11\n",
+			"include 10.64.0.0/10\n",
+			"include 10.128.0.0/9\n",
+			"include 11.0.0.0/8\n",
+			"include 12.0.0.0/6\n",
+			"include 16.0.0.0/4\n",
+			"include 32.0.0.0/3\n",
+			"include 64.0.0.0/2\n",
+			"include 128.0.0.0/1\n",
+		)
+	);
+	assert_eq!(
+		format_lines(Definition::compress_weighted(&rs, true, |_, _| 1)),
+		concat!(
+			"include 0.0.0.0/0\n",
+			"exclude 10.1.0.0/16\n",
+		)
+	);
+}
+
+#[test]
+fn compress_costs_ipv4_split() {
+	use set_def::RuleCosts;
+
+	// a /28 costs more than two /29s
+	let rs = ipv4_set(&["10.0.0.0/28"]);
+	let costs = RuleCosts{
+		per_length: vec![0; 28].into_iter().chain(Some(10)).collect(),
+		..Default::default()
+	};
+	assert_eq!(costs.split_cost(&"10.0.0.0/28".parse::<Ipv4Cidr>().unwrap(), true), 2);
+	assert_eq!(
+		format_lines(Definition::compress_costs(&rs, false, &costs)),
+		concat!(
+			"include 10.0.0.0/29\n",
+			"include 10.0.0.8/29\n",
+		)
+	);
+
+	// split /25 and /24 rules aren't cheaper than three /26
+	let rs = ipv4_set(&["10.0.0.64/26", "10.0.0.128/25"]);
+	let mut costs = RuleCosts::default();
+	costs.per_length.resize(26, 0);
+	costs.per_length[24] = 10;
+	costs.per_length[25] = 10;
+	let defs = Definition::compress_costs(&rs, false, &costs);
+	assert_eq!(Definition::verify(&defs, &rs, false), Ok(()));
+	assert_eq!(
+		format_lines(defs),
+		concat!(
+			"include 10.0.0.64/26\n",
+			"include 10.0.0.128/26\n",
+			"include 10.0.0.192/26\n",
+		)
+	);
+}
+
+#[test]
+fn compress_costs_ipv4_random() {
+	use cidr::Cidr;
+	use set_def::RuleCosts;
+
+	let mut x: u32 = 3;
+	let mut next = || {
+		x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+		x >> 8
+	};
+	for _ in 0..50 {
+		let mut rs = RadixSet::<Ipv4Cidr>::new();
+		for _ in 0..10 {
+			let len = 24 + (next() % 9) as u8;
+			let addr = 0x0a00_0000 | (next() & 0xff & (!0u32 << (32 - len)));
+			rs.insert(Ipv4Cidr::new(addr.into(), len).unwrap());
+		}
+		let costs = RuleCosts{
+			include: 1 + (next() % 3) as u64,
+			exclude: 1 + (next() % 3) as u64,
+			per_length: (0..33).map(|_| if 0 == next() % 4 { (next() % 20) as u64 } else { 0 }).collect(),
+		};
+		let total = |defs: &[Definition<Ipv4Cidr>]| defs.iter().map(|d| costs.cost(&d.prefix, d.label)).sum::<u64>();
+		for &invert in &[false, true] {
+			let defs = Definition::compress_costs(&rs, invert, &costs);
+			assert_eq!(Definition::verify(&defs, &rs, invert), Ok(()));
+			let weighted = Definition::compress_weighted(&rs, invert, |p, i| costs.cost(p, i));
+			assert!(total(&defs) <= total(&weighted));
+		}
+	}
+}
+
+// tests for prefix length limits
+
+#[test]
+fn compress_limited_ipv4_min() {
+	use limits::{LengthBounds,LengthLimits};
+
+	let rs = ipv4_set(&["10.0.0.0/8", "11.0.0.0/9"]);
+	let limits = LengthLimits::new(LengthBounds{ min: 9, max: None });
+	let limited = Definition::compress_limited(&rs, false, &limits).unwrap();
+	assert_eq!(
+		format_lines(&limited.definitions),
+		concat!(
+			"include 10.0.0.0/9\n",
+			"include 10.128.0.0/9\n",
+			"include 11.0.0.0/9\n",
+		)
+	);
+	assert!(limited.rounded.is_empty());
+	assert_eq!(limited.over_covered, 0);
+
+	// split includes, the exclude is only needed for the complete list
+	let rs = ipv4_set(&["0.0.0.0/1", "128.0.0.0/2"]);
+	let limits = LengthLimits::new(LengthBounds{ min: 2, max: None });
+	assert_eq!(
+		format_lines(Definition::compress_limited(&rs, false, &limits).unwrap().definitions),
+		concat!(
+			"include 0.0.0.0/2\n",
+			"include 64.0.0.0/2\n",
+			"include 128.0.0.0/2\n",
+		)
+	);
+	assert_eq!(
+		format_lines(Definition::complete_limited(&rs, false, &limits).unwrap().definitions),
+		concat!(
+			"include 0.0.0.0/2\n",
+			"include 64.0.0.0/2\n",
+			"include 128.0.0.0/2\n",
+			"exclude 192.0.0.0/2\n",
+		)
+	);
+}
+
+#[test]
+fn compress_limited_ipv4_split_limit() {
+	use limits::{LengthBounds,LengthLimits,LimitError,TooManyRules};
+
+	let rs = ipv4_set(&["10.0.0.0/8", "11.0.0.0/16"]);
+	let mut limits = LengthLimits{
+		split_limit: 257,
+		..LengthLimits::new(LengthBounds{ min: 16, max: None })
+	};
+	assert_eq!(Definition::compress_limited(&rs, false, &limits).unwrap().definitions.len(), 257);
+	// the split excludes count only for the complete list
+	assert_eq!(
+		Definition::complete_limited(&rs, false, &limits),
+		Err(LimitError::TooManyRules(TooManyRules{ count: 65536, limit: 257 }))
+	);
+
+	limits.ipv4.min = 32;
+	assert_eq!(
+		format!("{}", Definition::compress_limited(&rs, false, &limits).unwrap_err()),
+		"splitting short rules would generate 16842752 rules (limit 257)"
+	);
+}
+
+#[test]
+fn compress_limited_ipv4_max() {
+	use limits::{LengthBounds,LengthLimits,LimitError,TooLong,TooLongPolicy};
+
+	let rs = ipv4_set(&["10.0.0.0/8", "192.168.1.1/32", "192.168.1.128/25"]);
+	let mut limits = LengthLimits::new(LengthBounds{ min: 0, max: Some(24) });
+	assert_eq!(
+		Definition::compress_limited(&rs, false, &limits),
+		Err(LimitError::TooLong(TooLong{ prefix: Ipv4Cidr::from_str("192.168.1.1/32").unwrap(), max: 24 }))
+	);
+	assert_eq!(
+		format!("{}", Definition::compress_limited(&rs, false, &limits).unwrap_err()),
+		"192.168.1.1/32 is longer than /24"
+	);
+
+	limits.too_long = TooLongPolicy::RoundOutward;
+	let limited = Definition::compress_limited(&rs, false, &limits).unwrap();
+	assert_eq!(
+		format_lines(&limited.definitions),
+		concat!(
+			"include 10.0.0.0/8\n",
+			"include 192.168.1.0/24\n",
+		)
+	);
+	assert_eq!(limited.rounded, vec![
+		Ipv4Cidr::from_str("192.168.1.1/32").unwrap(),
+		Ipv4Cidr::from_str("192.168.1.128/25").unwrap(),
+	]);
+	assert_eq!(limited.over_covered, 127);
+
+	// inverted: the neighbours of the excluded prefixes are too long
+	let limited = Definition::compress_limited(&rs, true, &limits).unwrap();
+	assert_eq!(
+		format_lines(&limited.definitions),
+		concat!(
+			"include 0.0.0.0/0\n",
+			"exclude 10.0.0.0/8\n",
+		)
+	);
+	assert_eq!(limited.over_covered, 129);
+}
+
+#[test]
+fn compress_limited_any_per_family() {
+	use cidr::AnyIpCidr;
+	use limits::{LengthBounds,LengthLimits,LimitError,TooLong,TooLongPolicy};
+
+	let mut rs = RadixSet::<AnyIpCidr>::default();
+	for p in &["10.0.0.0/8", "192.168.1.1/32", "2001:db8::/32", "2001:db9:1:2::/64"] {
+		rs.insert(AnyIpCidr::from_str(p).unwrap());
+	}
+	let mut limits = LengthLimits{
+		ipv4: LengthBounds{ min: 0, max: Some(24) },
+		ipv6: LengthBounds{ min: 33, max: Some(48) },
+		..Default::default()
+	};
+	assert_eq!(
+		Definition::compress_limited(&rs, false, &limits),
+		Err(LimitError::TooLong(TooLong{ prefix: AnyIpCidr::from_str("192.168.1.1/32").unwrap(), max: 24 }))
+	);
+
+	limits.too_long = TooLongPolicy::RoundOutward;
+	let limited = Definition::compress_limited(&rs, false, &limits).unwrap();
+	assert_eq!(
+		format_lines(&limited.definitions),
+		concat!(
+			"include 10.0.0.0/8\n",
+			"include 192.168.1.0/24\n",
+			"include 2001:db8::/33\n",
+			"include 2001:db8:8000::/33\n",
+			"include 2001:db9:1::/48\n",
+		)
+	);
+	assert_eq!(limited.rounded, vec![
+		AnyIpCidr::from_str("192.168.1.1/32").unwrap(),
+		AnyIpCidr::from_str("2001:db9:1:2::/64").unwrap(),
+	]);
+
+	// the IPv6 bound must not apply to IPv4 prefixes
+	limits.ipv4.max = None;
+	limits.ipv6.max = Some(64);
+	limits.too_long = TooLongPolicy::Fail;
+	assert!(Definition::compress_limited(&rs, false, &limits).unwrap().rounded.is_empty());
+}
+
+// deaggregation
+
+#[test]
+fn deaggregate_ipv4() {
+	use deaggregate::{deaggregate,deaggregate_count,deaggregate_exact};
+
+	let rs = ipv4_set(&["10.0.0.0/22", "10.0.8.0/25", "192.168.0.0/24"]);
+	assert_eq!(deaggregate_count(&rs, 24), 6);
+	assert_eq!(
+		format_lines(deaggregate(&rs, 24).collect::<Vec<_>>()),
+		concat!(
+			"10.0.0.0/24\n",
+			"10.0.1.0/24\n",
+			"10.0.2.0/24\n",
+			"10.0.3.0/24\n",
+			"10.0.8.0/25\n",
+			"192.168.0.0/24\n",
+		)
+	);
+	assert_eq!(
+		format!("{}", deaggregate_exact(&rs, 24).err().unwrap()),
+		"10.0.8.0/25 is longer than /24"
+	);
+
+	let rs = ipv4_set(&["10.0.0.0/31"]);
+	assert_eq!(deaggregate_count(&rs, 40), 2);
+	assert_eq!(
+		format_lines(deaggregate_exact(&rs, 40).unwrap().collect::<Vec<_>>()),
+		"10.0.0.0/32\n10.0.0.1/32\n"
+	);
+}
+
+#[test]
+fn deaggregate_any() {
+	use cidr::AnyIpCidr;
+	use deaggregate::{deaggregate,deaggregate_count};
+
+	let mut rs = RadixSet::<AnyIpCidr>::default();
+	rs.insert(AnyIpCidr::Any);
+	assert_eq!(deaggregate_count(&rs, 1), 4);
+	assert_eq!(
+		deaggregate(&rs, 1).map(|p| p.to_string()).collect::<Vec<_>>(),
+		vec!["0.0.0.0/1", "128.0.0.0/1", "::/1", "8000::/1"]
+	);
+	assert_eq!(deaggregate_count(&rs, 128), u128::MAX);
+}
+
+// address families
+
+#[test]
+fn ip_prefix_lengths() {
+	use cidr::{AnyIpCidr,Cidr,Ipv6Cidr};
+	use prefix::IpPrefix;
+
+	// both traits in scope: the method names must not clash
+	let p = Ipv4Cidr::from_str("10.0.0.0/8").unwrap();
+	assert_eq!(p.network_length(), 8);
+	assert_eq!(p.prefix_network_length(), Some(8));
+	assert_eq!(p.prefix_address_length(), Some(32));
+	assert_eq!(p.address_count(), 1 << 24);
+	assert_eq!(Ipv6Cidr::from_str("2001:db8::/32").unwrap().prefix_address_length(), Some(128));
+	assert_eq!(AnyIpCidr::from_str("10.0.0.0/8").unwrap().prefix_network_length(), Some(8));
+	assert_eq!(AnyIpCidr::Any.prefix_network_length(), None);
+	assert_eq!(AnyIpCidr::Any.address_count(), u128::MAX);
+}
+
+#[test]
+fn family_split() {
+	use cidr::AnyIpCidr;
+	use family::split;
+
+	let mut rs = RadixSet::<AnyIpCidr>::default();
+	for p in &["10.0.0.0/8", "2001:db8::/32", "192.168.0.0/16", "2001:db8:1::/48"] {
+		rs.insert(AnyIpCidr::from_str(p).unwrap());
+	}
+	let (ipv4, ipv6) = split(&rs);
+	assert_eq!(set_lines(&ipv4), "10.0.0.0/8\n192.168.0.0/16\n");
+	assert_eq!(set_lines(&ipv6), "2001:db8::/32\n");
+
+	rs.insert(AnyIpCidr::Any);
+	let (ipv4, ipv6) = split(&rs);
+	assert_eq!(set_lines(&ipv4), "0.0.0.0/0\n");
+	assert_eq!(set_lines(&ipv6), "::/0\n");
+}
+
+// statistics
+
+#[test]
+fn stats_any() {
+	use cidr::AnyIpCidr;
+	use stats::SetStats;
+
+	let mut rs = RadixSet::<AnyIpCidr>::default();
+	for p in &["10.0.0.0/8", "10.1.0.0/16", "192.168.0.1", "2001:db8::/48", "2001:db8:1::1", "2001:db8:1::2/127"] {
+		rs.insert(AnyIpCidr::from_str(p).unwrap());
+	}
+	let stats = SetStats::new(&rs);
+	assert_eq!(stats.ipv4.prefixes, 2);
+	assert_eq!(stats.ipv4.addresses, (1 << 24) + 1);
+	assert_eq!(stats.ipv4.lengths[8], 1);
+	assert_eq!(stats.ipv4.lengths[16], 0);
+	assert_eq!(stats.ipv4.lengths[32], 1);
+	assert_eq!(stats.ipv6.prefixes, 3);
+	assert_eq!(stats.ipv6.addresses, (1 << 80) + 3);
+	assert_eq!(stats.ipv6.lengths.iter().sum::<usize>(), 3);
+	assert_eq!(stats.ipv6_networks, (1 << 16) + 1);
+	assert_eq!(::stats::estimated_memory(&rs), 9 * ::std::mem::size_of::<::bitstring_trees::set::Node<AnyIpCidr>>());
+
+	let mut rs = RadixSet::<AnyIpCidr>::default();
+	rs.insert(AnyIpCidr::Any);
+	let stats = SetStats::new(&rs);
+	assert_eq!(stats.ipv4.addresses, 1 << 32);
+	assert_eq!(stats.ipv6.addresses, u128::MAX);
+	assert_eq!(stats.ipv6_networks, 1 << 64);
+}
+
+// diff
+
+#[test]
+fn diff_ipv4() {
+	use diff::diff;
+
+	let old = ipv4_set(&["10.0.0.0/8", "192.168.0.0/16"]);
+	let new = ipv4_set(&["10.0.0.0/9", "172.16.0.0/12", "192.168.0.0/24", "192.168.1.0/24"]);
+	let d = diff(&old, &new);
+	assert_eq!(
+		format_lines(&d.removed),
+		concat!(
+			"10.128.0.0/9\n",
+			"192.168.2.0/23\n",
+			"192.168.4.0/22\n",
+			"192.168.8.0/21\n",
+			"192.168.16.0/20\n",
+			"192.168.32.0/19\n",
+			"192.168.64.0/18\n",
+			"192.168.128.0/17\n",
+		)
+	);
+	assert_eq!(format_lines(&d.added), "172.16.0.0/12\n");
+	assert_eq!(d.removed_addresses, (1 << 23) + (1 << 16) - 512);
+	assert_eq!(d.added_addresses, 1 << 20);
+
+	let d = diff(&old, &old);
+	assert!(d.removed.is_empty() && d.added.is_empty());
+	assert_eq!((d.removed_addresses, d.added_addresses), (0, 0));
+}
+
+// updates
+
+#[test]
+fn compress_update_ipv4() {
+	let old = ipv4_defs(&[
+		"include 10.0.0.0/8",
+		"exclude 10.1.0.0/16",
+		"include 10.1.2.0/24",
+		"include 192.168.0.0/16",
+		"exclude 192.168.5.0/24",
+	]);
+
+	// unchanged set: nothing to do
+	let update = Definition::compress_update(&Definition::evaluate(&old), false, &old);
+	assert_eq!(update.definitions, old);
+	assert!(update.added.is_empty() && update.deleted.is_empty());
+
+	// the neutral include of 10.1.2.0/24 is kept
+	let rs = ipv4_set(&["10.0.0.0/8", "192.168.0.0/16"]);
+	let update = Definition::compress_update(&rs, false, &old);
+	assert_eq!(
+		format_lines(&update.definitions),
+		concat!(
+			"include 10.0.0.0/8\n",
+			"include 10.1.2.0/24\n",
+			"include 192.168.0.0/16\n",
+		)
+	);
+	assert!(update.added.is_empty());
+	assert_eq!(
+		format_lines(&update.deleted),
+		concat!(
+			"exclude 10.1.0.0/16\n",
+			"exclude 192.168.5.0/24\n",
+		)
+	);
+
+	let rs = ipv4_set(&["10.0.0.0/8", "172.16.0.0/12"]);
+	let update = Definition::compress_update(&rs, false, &old);
+	assert_eq!(format_lines(&update.added), "include 172.16.0.0/12\n");
+	Definition::verify(&update.definitions, &rs, false).unwrap();
+}
+
+#[test]
+fn compress_update_ipv4_equivalent() {
+	use cidr::Cidr;
+
+	// old rules describing the set differently are kept
+	let old = ipv4_defs(&[
+		"include 10.0.0.0/24",
+		"exclude 10.0.0.0/26",
+		"exclude 10.0.0.64/26",
+	]);
+	let update = Definition::compress_update(&ipv4_set(&["10.0.0.128/25"]), false, &old);
+	assert_eq!(update.definitions, old);
+	assert!(update.added.is_empty() && update.deleted.is_empty());
+
+	let old = ipv4_defs(&["include 10.0.0.0/29", "include 10.0.0.8/29"]);
+	let update = Definition::compress_update(&ipv4_set(&["10.0.0.0/28"]), false, &old);
+	assert_eq!(update.definitions, old);
+	assert!(update.added.is_empty() && update.deleted.is_empty());
+
+	// inverted
+	let old = ipv4_defs(&["include 0.0.0.0/1", "exclude 10.0.0.0/28", "include 128.0.0.0/1"]);
+	let update = Definition::compress_update(&ipv4_set(&["10.0.0.0/28"]), true, &old);
+	assert_eq!(update.definitions, old);
+	assert!(update.added.is_empty() && update.deleted.is_empty());
+
+	// random deployed lists of the set
+	let mut x: u32 = 5;
+	let mut next = || {
+		x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+		x >> 8
+	};
+	for _ in 0..50 {
+		let old : Vec<_> = (0..8).map(|_| {
+			let len = 24 + (next() % 9) as u8;
+			let addr = 0x0a00_0000 | (next() & 0xff & (!0u32 << (32 - len)));
+			Definition{
+				prefix: Ipv4Cidr::new(addr.into(), len).unwrap(),
+				label: 0 == next() % 2,
+			}
+		}).collect();
+		let rs = Definition::evaluate(&old);
+		let update = Definition::compress_update(&rs, false, &old);
+		assert!(update.added.is_empty(), "{:?}", old);
+		Definition::verify(&update.definitions, &rs, false).unwrap();
+	}
+}
+
+// incremental compression
+
+#[test]
+fn incremental_ipv4() {
+	use incremental::IncrementalSet;
+
+	let mut inc = IncrementalSet::new();
+	for p in &["10.0.0.0/9", "10.128.0.0/9", "192.168.0.0/16"] {
+		inc.insert(Ipv4Cidr::from_str(p).unwrap());
+	}
+	inc.remove(&Ipv4Cidr::from_str("10.1.0.0/16").unwrap());
+	inc.remove(&Ipv4Cidr::from_str("192.168.0.0/17").unwrap());
+	let rs = ipv4_set(&["10.0.0.0/8", "192.168.128.0/17"]);
+	let rs = ::set_ops::difference(&rs, &ipv4_set(&["10.1.0.0/16"]));
+	for &invert in &[false, true] {
+		assert_eq!(inc.compress(invert), Definition::compress(&rs, invert));
+		assert_eq!(inc.compress_len(invert), inc.compress(invert).len());
+	}
+	assert_eq!(
+		format_lines(inc.compress(false)),
+		concat!(
+			"include 10.0.0.0/8\n",
+			"exclude 10.1.0.0/16\n",
+			"include 192.168.128.0/17\n",
+		)
+	);
+
+	inc.remove(&Ipv4Cidr::from_str("0.0.0.0/0").unwrap());
+	assert!(inc.is_empty());
+	assert_eq!(inc.compress(false), Vec::new());
+}
+
+#[test]
+fn incremental_ipv4_random() {
+	use cidr::Cidr;
+	use incremental::IncrementalSet;
+
+	// pseudo random insert/remove sequences of overlapping prefixes
+	// within 10.0.0.0/16
+	let mut x: u32 = 1;
+	let mut next = || {
+		x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+		x >> 8
+	};
+	for _ in 0..50 {
+		let mut inc = IncrementalSet::new();
+		let mut rs = RadixSet::<Ipv4Cidr>::new();
+		for _ in 0..40 {
+			let len = 16 + (next() % 13) as u8;
+			let addr = 0x0a00_0000 | (next() & 0xffff & (!0u32 << (32 - len)));
+			let prefix = Ipv4Cidr::new(addr.into(), len).unwrap();
+			if 0 == next() % 3 {
+				inc.remove(&prefix);
+				let mut removed = RadixSet::new();
+				removed.insert(prefix);
+				rs = ::set_ops::difference(&rs, &removed);
+			} else {
+				inc.insert(prefix.clone());
+				rs.insert(prefix);
+			}
+			for &invert in &[false, true] {
+				let defs = inc.compress(invert);
+				assert_eq!(defs, Definition::compress(&rs, invert));
+				assert_eq!(inc.compress_len(invert), defs.len());
+			}
+		}
+	}
+}
+
+#[test]
+fn incremental_ipv4_changes() {
+	use cidr::Cidr;
+	use incremental::IncrementalSet;
+	use update::cmp_definitions;
+
+	let mut x: u32 = 7;
+	let mut next = || {
+		x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+		x >> 8
+	};
+	for _ in 0..50 {
+		let mut inc = IncrementalSet::new();
+		let mut rs = RadixSet::<Ipv4Cidr>::new();
+		// the list built from the changes
+		let mut defs = Vec::new();
+		let mut invert = false;
+		for _ in 0..40 {
+			let len = 16 + (next() % 13) as u8;
+			let addr = 0x0a00_0000 | (next() & 0xffff & (!0u32 << (32 - len)));
+			let prefix = Ipv4Cidr::new(addr.into(), len).unwrap();
+			if 0 == next() % 3 {
+				inc.remove(&prefix);
+				let mut removed = RadixSet::new();
+				removed.insert(prefix);
+				rs = ::set_ops::difference(&rs, &removed);
+			} else {
+				inc.insert(prefix.clone());
+				rs.insert(prefix);
+			}
+			if 0 == next() % 8 {
+				invert = !invert;
+			}
+			let changes = inc.changes(invert);
+			for def in &changes.deleted {
+				let pos = defs.iter().position(|d| d == def).expect("deleted definition not in list");
+				defs.remove(pos);
+			}
+			defs.extend(changes.added);
+			defs.sort_by(cmp_definitions);
+			let mut expected = Definition::compress(&rs, invert);
+			expected.sort_by(cmp_definitions);
+			assert_eq!(defs, expected);
+			assert!(inc.changes(invert).added.is_empty());
+		}
+	}
+}
+
+#[test]
+fn incremental_ipv4_changes_path() {
+	use incremental::IncrementalSet;
+
+	let mut inc = IncrementalSet::from(&ipv4_set(&["10.0.0.0/24", "10.0.2.0/24", "10.0.4.0/24"]));
+	assert_eq!(inc.changes(false).added.len(), 3);
+	inc.insert("10.0.1.0/24".parse().unwrap());
+	let changes = inc.changes(false);
+	assert_eq!(changes.added, ipv4_defs(&["include 10.0.0.0/23"]));
+	assert_eq!(changes.deleted, ipv4_defs(&["include 10.0.0.0/24"]));
+	inc.remove(&"10.0.4.0/24".parse().unwrap());
+	let changes = inc.changes(false);
+	assert!(changes.added.is_empty());
+	assert_eq!(changes.deleted, ipv4_defs(&["include 10.0.4.0/24"]));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn compress_parallel_ipv4() {
+	use cidr::Cidr;
+
+	let mut set = ipv4_set(&["10.0.0.0/8", "192.168.0.0/16"]);
+	// pseudo random /24 networks
+	let mut x: u32 = 1;
+	for _ in 0..2000 {
+		x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+		set.insert(Ipv4Cidr::new((x & 0xffff_ff00).into(), 24).unwrap());
+	}
+	let set = ::set_ops::difference(&set, &ipv4_set(&["10.1.0.0/16", "192.168.1.128/25"]));
+	for &invert in &[false, true] {
+		let expected = Definition::compress(&set, invert);
+		for &threads in &[1, 2, 3, 8] {
+			assert_eq!(Definition::compress_parallel(&set, invert, threads), expected);
+		}
+	}
+	assert_eq!(Definition::compress_parallel(&RadixSet::<Ipv4Cidr>::new(), true, 4), Definition::compress(&RadixSet::new(), true));
+}
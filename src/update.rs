@@ -0,0 +1,192 @@
+//! updates of a previously deployed definition list with few changes
+use bitstring::BitString;
+use bitstring_trees::set::RadixSet;
+use set_def::{self,Definition};
+use std::cmp::Ordering;
+
+/// Result of `Definition::compress_update`
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct Update<S: BitString> {
+	/// the new definitions (sorted lexicographically)
+	pub definitions: Vec<Definition<S>>,
+	/// new definitions which are not in the old list
+	pub added: Vec<Definition<S>>,
+	/// definitions of the old list which are not in the new one
+	pub deleted: Vec<Definition<S>>,
+}
+
+pub(crate) fn cmp_definitions<S: BitString>(a: &Definition<S>, b: &Definition<S>) -> Ordering {
+	a.prefix.lexicographic_cmp(&b.prefix).then(a.label.cmp(&b.label))
+}
+
+// whether `a` contains `b`
+fn contains<S: BitString>(a: &S, b: &S) -> bool {
+	a.len() <= b.len() && a.len() == a.shared_prefix_len(b)
+}
+
+// minimal cost of the definitions for a region, depending on whether
+// it is excluded (`cost.0`) or included (`cost.1`) by the parent
+// definitions; regions without children are uniform (`label`)
+struct Region {
+	cost: (u64, u64),
+	label: bool,
+	children: Option<Box<(Region, Region)>>,
+}
+
+// half of `prefix`
+fn child<S: BitString+Clone>(prefix: &S, bit: bool) -> S {
+	let mut child = prefix.clone();
+	child.append(bit);
+	child
+}
+
+impl Region {
+	// `keys`: sorted prefixes within `prefix` which are either a
+	// prefix of the set (`true`) or only candidates for definitions;
+	// `inside`: whether a prefix of the set contains `prefix`
+	fn new<S, F>(prefix: &S, keys: &[(S, bool)], inside: bool, invert: bool, cost: &F) -> Self
+	where
+		S: BitString+Clone,
+		F: Fn(&S, bool) -> u64,
+	{
+		let (keys, inside) = match keys.first() {
+			Some(key) if key.0.len() == prefix.len() => (&keys[1..], inside || key.1),
+			_ => (keys, inside),
+		};
+		if keys.is_empty() {
+			let label = inside != invert;
+			let c = cost(prefix, label);
+			return Region{
+				cost: if label { (c, 0) } else { (0, c) },
+				label,
+				children: None,
+			};
+		}
+		let split = keys.iter().position(|key| key.0.get(prefix.len())).unwrap_or(keys.len());
+		let left = Region::new(&child(prefix, false), &keys[..split], inside, invert, cost);
+		let right = Region::new(&child(prefix, true), &keys[split..], inside, invert, cost);
+		let keep = (left.cost.0.saturating_add(right.cost.0), left.cost.1.saturating_add(right.cost.1));
+		Region{
+			cost: set_def::switch_cost(prefix, keep, cost),
+			label: false,
+			children: Some(Box::new((left, right))),
+		}
+	}
+
+	fn emit<S: BitString+Clone>(&self, prefix: S, include: bool, list: &mut Vec<Definition<S>>) {
+		let children = match self.children {
+			None => {
+				if include != self.label {
+					list.push(Definition{
+						prefix,
+						label: self.label,
+					});
+				}
+				return;
+			},
+			Some(ref children) => children,
+		};
+		let (ref left, ref right) = **children;
+		let (keep, target) = if include {
+			(left.cost.1.saturating_add(right.cost.1), self.cost.1)
+		} else {
+			(left.cost.0.saturating_add(right.cost.0), self.cost.0)
+		};
+		// prefer not to switch context (keeps excludes longer)
+		let include = if keep == target {
+			include
+		} else {
+			list.push(Definition{
+				prefix: prefix.clone(),
+				label: !include,
+			});
+			!include
+		};
+		left.emit(child(&prefix, false), include, list);
+		right.emit(child(&prefix, true), include, list);
+	}
+}
+
+impl<S: BitString+Clone> Definition<S> {
+	/// Same as `compress`, but prefers definitions from a previously
+	/// deployed list `old` to keep the changes small.
+	///
+	/// The number of added definitions is minimised first, then the
+	/// total number of definitions; the prefixes of old definitions
+	/// are candidates even where the set doesn't need a definition
+	/// (e.g. two old includes for the halves of a new prefix are
+	/// kept).  Afterwards all old definitions which don't change the result
+	/// (i.e. match the longest containing new definition, or exclude
+	/// addresses without one) are kept too.
+	pub fn compress_update(s: &RadixSet<S>, invert: bool, old: &[Definition<S>]) -> Update<S> {
+		let mut old = old.to_vec();
+		old.sort_by(cmp_definitions);
+		old.dedup_by(|b, a| cmp_definitions(a, b).is_eq());
+		let in_old = |prefix: &S, include: bool| old.binary_search_by(|def| {
+			def.prefix.lexicographic_cmp(prefix).then(def.label.cmp(&include))
+		}).is_ok();
+
+		// a single added definition costs more than keeping all old ones
+		let add_cost = old.len() as u64 + 1;
+		let cost = |prefix: &S, include: bool| {
+			if in_old(prefix, include) { 1 } else { add_cost }
+		};
+		let mut keys : Vec<(S, bool)> = s.iter().map(|prefix| (prefix.clone(), true))
+			.chain(old.iter().map(|def| (def.prefix.clone(), false)))
+			.collect();
+		// the set prefix first if an old definition has the same one
+		keys.sort_by(|a, b| a.0.lexicographic_cmp(&b.0).then(b.1.cmp(&a.1)));
+		keys.dedup_by(|b, a| a.0.lexicographic_cmp(&b.0).is_eq());
+		let mut definitions = Vec::new();
+		Region::new(&S::null(), &keys, false, invert, &cost).emit(S::null(), false, &mut definitions);
+		definitions.sort_by(cmp_definitions);
+		let added : Vec<_> = definitions.iter()
+			.filter(|def| !in_old(&def.prefix, def.label))
+			.cloned()
+			.collect();
+
+		// walk both lists in lexicographic order, with the stack of new
+		// definitions containing the current old one
+		let mut kept = Vec::new();
+		let mut deleted = Vec::new();
+		{
+			let mut stack : Vec<&Definition<S>> = Vec::new();
+			let mut new_defs = definitions.iter().peekable();
+			for def in &old {
+				while let Some(next) = new_defs.next_if(|next| next.prefix.lexicographic_cmp(&def.prefix).is_le()) {
+					while stack.last().is_some_and(|top| !contains(&top.prefix, &next.prefix)) {
+						stack.pop();
+					}
+					stack.push(next);
+				}
+				while stack.last().is_some_and(|top| !contains(&top.prefix, &def.prefix)) {
+					stack.pop();
+				}
+				match stack.last() {
+					Some(top) if top.prefix.lexicographic_cmp(&def.prefix).is_eq() => {
+						if top.label != def.label {
+							deleted.push(def.clone());
+						}
+					},
+					top => {
+						if top.is_some_and(|top| top.label) == def.label {
+							kept.push(def.clone());
+						} else {
+							deleted.push(def.clone());
+						}
+					},
+				}
+			}
+		}
+
+		if !kept.is_empty() {
+			definitions.extend(kept);
+			definitions.sort_by(cmp_definitions);
+		}
+		Update{
+			definitions,
+			added,
+			deleted,
+		}
+	}
+}
@@ -6,7 +6,7 @@ pub struct WriteLinesIter<I: Iterator+Clone> {
 
 impl<I: Iterator+Clone> WriteLinesIter<I> {
 	pub fn new(iter: I) -> Self {
-		WriteLinesIter{iter: iter}
+		WriteLinesIter{iter}
 	}
 }
 
@@ -19,7 +19,7 @@ impl<T, I: Iterator<Item=T>+Clone, F: IntoIterator<Item=T,IntoIter=I>> From<F> f
 impl<T: fmt::Display, I: Iterator<Item=T>+Clone> fmt::Display for WriteLinesIter<I> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		for x in self.iter.clone() {
-			try!(writeln!(f, "{}", x));
+			writeln!(f, "{}", x)?;
 		}
 		Ok(())
 	}
@@ -28,7 +28,7 @@ impl<T: fmt::Display, I: Iterator<Item=T>+Clone> fmt::Display for WriteLinesIter
 impl<T: fmt::Debug, I: Iterator<Item=T>+Clone> fmt::Debug for WriteLinesIter<I> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		for x in self.iter.clone() {
-			try!(writeln!(f, "{:?}", x));
+			writeln!(f, "{:?}", x)?;
 		}
 		Ok(())
 	}